@@ -23,10 +23,11 @@ pub async fn upload(parts: awmp::Parts) -> Result<HttpResponse, Error> {
 
 #[actix_rt::main]
 async fn main() -> Result<(), std::io::Error> {
+    let parts_config = awmp::PartsConfig::default().with_file_limit(100000);
+    parts_config.validate().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
     HttpServer::new(move || {
-        App::new()
-            .app_data(awmp::PartsConfig::default().with_file_limit(100000))
-            .route("/", web::post().to(upload))
+        App::new().app_data(parts_config.clone()).route("/", web::post().to(upload))
     })
     .bind("0.0.0.0:3000")?
     .run()
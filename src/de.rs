@@ -0,0 +1,336 @@
+//! Loading `PartsConfig` from configuration files
+//!
+//! Enabled with the `serde` feature. Sizes may be given in bytes or as strings such as
+//! `"10MB"` (see `parse_size`), and durations in seconds or as strings such as `"500ms"`,
+//! `"30s"` or `"2m"`. Every key is optional; anything left out keeps the value
+//! from `PartsConfig::recommended()`, or from `PartsConfig::unlimited()` when `unlimited = true`.
+//! Settings that hold code (field filters and routers) or compiled patterns are left to the
+//! builder methods. `temp_dir_mode` and `temp_file_mode` are accepted but ignored off Unix, so
+//! the same file can be used on every platform.
+
+use super::*;
+use serde::{de, Deserialize, Deserializer};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PartsConfigDef {
+    #[serde(default)]
+    unlimited: bool,
+    text_limit: Option<Size>,
+    text_overflow_policy: Option<TextOverflowPolicy>,
+    total_text_limit: Option<Size>,
+    trim_text: Option<bool>,
+    strip_bom: Option<bool>,
+    normalize_newlines: Option<bool>,
+    strict_utf8: Option<bool>,
+    #[cfg(feature = "encoding_rs")]
+    text_charset_decoding: Option<bool>,
+    #[cfg(feature = "base64")]
+    base64_text_decoding: Option<bool>,
+    file_limit: Option<Size>,
+    total_limit: Option<Size>,
+    oversize_policy: Option<OversizePolicy>,
+    max_files: Option<usize>,
+    max_fields: Option<usize>,
+    max_parts: Option<usize>,
+    max_field_name_len: Option<usize>,
+    max_file_name_len: Option<usize>,
+    max_part_header_size: Option<Size>,
+    long_name_policy: Option<LongNamePolicy>,
+    file_fields: Option<Vec<String>>,
+    text_fields: Option<Vec<String>>,
+    case_insensitive_fields: Option<bool>,
+    normalize_array_fields: Option<bool>,
+    only_fields: Option<Vec<String>>,
+    required_fields: Option<Vec<String>>,
+    sensitive_fields: Option<Vec<String>>,
+    duplicate_policy: Option<DuplicatePolicy>,
+    error_mode: Option<ErrorMode>,
+    allowed_mime_types: Option<Vec<String>>,
+    denied_mime_types: Option<Vec<String>>,
+    allowed_extensions: Option<Vec<String>>,
+    disallowed_file_policy: Option<DisallowedFilePolicy>,
+    discard_files: Option<bool>,
+    skip_empty_files: Option<bool>,
+    strict_content_types: Option<bool>,
+    in_memory_only: Option<Size>,
+    memory_threshold: Option<Size>,
+    temp_dir: Option<PathBuf>,
+    temp_quota: Option<Size>,
+    create_temp_dir: Option<bool>,
+    temp_dir_mode: Option<u32>,
+    temp_file_prefix: Option<String>,
+    temp_file_suffix: Option<String>,
+    temp_file_mode: Option<u32>,
+    tiered_temp_dirs: Option<Vec<(Size, PathBuf)>>,
+    #[cfg(feature = "fs2")]
+    min_free_space: Option<Size>,
+    temp_dir_for: Option<Vec<(String, PathBuf)>>,
+    #[cfg(not(feature = "v1"))]
+    deadline: Option<Secs>,
+    #[cfg(not(feature = "v1"))]
+    field_timeout: Option<Secs>,
+    #[cfg(not(feature = "v1"))]
+    min_transfer_rate: Option<(Size, Secs)>,
+    #[cfg(not(feature = "v1"))]
+    max_transfer_rate: Option<Size>,
+}
+
+/// A size given either as a number of bytes or as a string like `"10MB"` (see `parse_size`)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Size {
+    Bytes(usize),
+    Text(String),
+}
+
+impl Size {
+    fn bytes<E: de::Error>(self) -> Result<usize, E> {
+        match self {
+            Size::Bytes(x) => Ok(x),
+            Size::Text(x) => parse_size(&x).map_err(E::custom),
+        }
+    }
+}
+
+fn parse_size_opt<E: de::Error>(size: Option<Size>) -> Result<Option<usize>, E> {
+    size.map(Size::bytes).transpose()
+}
+
+/// A duration given either as a number of seconds or as a string like `"30s"`
+#[cfg(not(feature = "v1"))]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Secs {
+    Secs(f64),
+    Text(String),
+}
+
+#[cfg(not(feature = "v1"))]
+impl Secs {
+    fn duration<E: de::Error>(self) -> Result<Duration, E> {
+        let invalid = |x: &str| E::custom(format!("invalid duration `{}`", x));
+        let secs = match self {
+            Secs::Secs(x) => x,
+            Secs::Text(x) => {
+                let s = x.trim();
+                let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+                let (number, unit) = s.split_at(split);
+                let number = number.parse::<f64>().map_err(|_| invalid(&x))?;
+                match unit.trim() {
+                    "ms" => number / 1000.0,
+                    "" | "s" => number,
+                    "m" => number * 60.0,
+                    "h" => number * 3600.0,
+                    _ => return Err(invalid(&x)),
+                }
+            }
+        };
+        if secs.is_finite() && secs >= 0.0 {
+            Ok(Duration::from_secs_f64(secs))
+        } else {
+            Err(invalid(&secs.to_string()))
+        }
+    }
+}
+
+#[cfg(not(feature = "v1"))]
+fn parse_secs_opt<E: de::Error>(secs: Option<Secs>) -> Result<Option<Duration>, E> {
+    secs.map(Secs::duration).transpose()
+}
+
+fn parse_mime_types<E: de::Error>(mime_types: Vec<String>) -> Result<Vec<mime::Mime>, E> {
+    mime_types
+        .iter()
+        .map(|x| x.parse().map_err(|_| E::custom(format!("invalid MIME type `{}`", x))))
+        .collect()
+}
+
+impl<'de> Deserialize<'de> for PartsConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let def = PartsConfigDef::deserialize(deserializer)?;
+
+        let mut cfg =
+            if def.unlimited { PartsConfig::unlimited() } else { PartsConfig::recommended() };
+
+        cfg.text_limit = parse_size_opt(def.text_limit)?.or(cfg.text_limit);
+        cfg.text_overflow_policy = def.text_overflow_policy.or(cfg.text_overflow_policy);
+        cfg.total_text_limit = parse_size_opt(def.total_text_limit)?.or(cfg.total_text_limit);
+        cfg.trim_text = def.trim_text.unwrap_or(cfg.trim_text);
+        cfg.strip_bom = def.strip_bom.unwrap_or(cfg.strip_bom);
+        cfg.normalize_newlines = def.normalize_newlines.unwrap_or(cfg.normalize_newlines);
+        cfg.strict_utf8 = def.strict_utf8.unwrap_or(cfg.strict_utf8);
+        #[cfg(feature = "encoding_rs")]
+        {
+            cfg.decode_text_charsets =
+                def.text_charset_decoding.unwrap_or(cfg.decode_text_charsets);
+        }
+        #[cfg(feature = "base64")]
+        {
+            cfg.decode_base64_texts = def.base64_text_decoding.unwrap_or(cfg.decode_base64_texts);
+        }
+        cfg.file_limit = parse_size_opt(def.file_limit)?.or(cfg.file_limit);
+        cfg.total_limit = parse_size_opt(def.total_limit)?.or(cfg.total_limit);
+        cfg.oversize_policy = def.oversize_policy.or(cfg.oversize_policy);
+        cfg.max_files = def.max_files.or(cfg.max_files);
+        cfg.max_fields = def.max_fields.or(cfg.max_fields);
+        cfg.max_parts = def.max_parts.or(cfg.max_parts);
+        cfg.max_field_name_len = def.max_field_name_len.or(cfg.max_field_name_len);
+        cfg.max_file_name_len = def.max_file_name_len.or(cfg.max_file_name_len);
+        cfg.max_part_header_size =
+            parse_size_opt(def.max_part_header_size)?.or(cfg.max_part_header_size);
+        cfg.long_name_policy = def.long_name_policy.or(cfg.long_name_policy);
+        cfg.case_insensitive_fields =
+            def.case_insensitive_fields.unwrap_or(cfg.case_insensitive_fields);
+        cfg.normalize_array_fields =
+            def.normalize_array_fields.unwrap_or(cfg.normalize_array_fields);
+        cfg.duplicate_policy = def.duplicate_policy.or(cfg.duplicate_policy);
+        cfg.error_mode = def.error_mode.or(cfg.error_mode);
+        cfg.disallowed_file_policy = def.disallowed_file_policy.or(cfg.disallowed_file_policy);
+        cfg.discard_files = def.discard_files.unwrap_or(cfg.discard_files);
+        cfg.skip_empty_files = def.skip_empty_files.unwrap_or(cfg.skip_empty_files);
+        cfg.strict_content_types = def.strict_content_types.unwrap_or(cfg.strict_content_types);
+        cfg.in_memory_limit = parse_size_opt(def.in_memory_only)?.or(cfg.in_memory_limit);
+        cfg.memory_threshold = parse_size_opt(def.memory_threshold)?.or(cfg.memory_threshold);
+
+        if let Some(x) = def.file_fields {
+            cfg = cfg.with_file_fields(x);
+        }
+        if let Some(x) = def.text_fields {
+            cfg = cfg.with_text_fields(x);
+        }
+        if let Some(x) = def.only_fields {
+            cfg = cfg.with_only_fields(x);
+        }
+        if let Some(x) = def.required_fields {
+            cfg = cfg.with_required_fields(x);
+        }
+        if let Some(x) = def.sensitive_fields {
+            cfg = cfg.with_sensitive_fields(x);
+        }
+        if let Some(x) = def.allowed_mime_types {
+            cfg = cfg.with_allowed_mime_types(parse_mime_types(x)?);
+        }
+        if let Some(x) = def.denied_mime_types {
+            cfg = cfg.with_denied_mime_types(parse_mime_types(x)?);
+        }
+        if let Some(x) = def.allowed_extensions {
+            cfg = cfg.with_allowed_extensions(x);
+        }
+        if let Some(x) = def.temp_dir {
+            cfg = cfg.with_temp_dir(x);
+        }
+        if let Some(x) = def.temp_file_prefix {
+            cfg = cfg.with_temp_file_prefix(x);
+        }
+        if let Some(x) = def.temp_file_suffix {
+            cfg = cfg.with_temp_file_suffix(x);
+        }
+        cfg.temp_quota = parse_size_opt(def.temp_quota)?.or(cfg.temp_quota);
+        cfg.create_temp_dir = def.create_temp_dir.unwrap_or(cfg.create_temp_dir);
+        #[cfg(unix)]
+        {
+            cfg.temp_dir_mode = def.temp_dir_mode.or(cfg.temp_dir_mode);
+            cfg.temp_file_mode = def.temp_file_mode.or(cfg.temp_file_mode);
+        }
+        #[cfg(not(unix))]
+        let _ = (def.temp_dir_mode, def.temp_file_mode);
+        if let Some(x) = def.tiered_temp_dirs {
+            let tiers = x
+                .into_iter()
+                .map(|(x, dir)| Ok((x.bytes()?, dir)))
+                .collect::<Result<Vec<_>, D::Error>>()?;
+            cfg = cfg.with_tiered_temp_dirs(tiers);
+        }
+        #[cfg(feature = "fs2")]
+        {
+            cfg.min_free_space = parse_size_opt(def.min_free_space)?.or(cfg.min_free_space);
+        }
+        for (name, dir) in def.temp_dir_for.into_iter().flatten() {
+            cfg = cfg.with_temp_dir_for(&name, dir);
+        }
+        #[cfg(not(feature = "v1"))]
+        {
+            cfg.deadline = parse_secs_opt(def.deadline)?.or(cfg.deadline);
+            cfg.field_timeout = parse_secs_opt(def.field_timeout)?.or(cfg.field_timeout);
+            if let Some((rate, grace_period)) = def.min_transfer_rate {
+                cfg.min_transfer_rate = Some((rate.bytes()?, grace_period.duration()?));
+            }
+            cfg.max_transfer_rate =
+                parse_size_opt(def.max_transfer_rate)?.or(cfg.max_transfer_rate);
+        }
+
+        Ok(cfg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn config_is_deserialized_over_defaults() {
+        let cfg: PartsConfig = serde_json::from_str(
+            r#"{
+                "file_limit": 1024,
+                "total_limit": "10MB",
+                "oversize_policy": "reject_request",
+                "required_fields": ["title"],
+                "allowed_mime_types": ["image/*"]
+            }"#,
+        )
+        .expect("Failed deserializing config");
+
+        assert_eq!(cfg.file_limit, Some(1024));
+        assert_eq!(cfg.total_limit, Some(10_000_000));
+        assert_eq!(cfg.text_limit, PartsConfig::recommended().text_limit);
+        assert_eq!(cfg.oversize_policy, Some(OversizePolicy::RejectRequest));
+        assert_eq!(cfg.required_fields.as_deref(), Some(&["title".to_string()][..]));
+        assert!(cfg.allowed_mime_types.is_some());
+        assert!(cfg.validate().is_ok());
+
+        let cfg: PartsConfig = serde_json::from_str(r#"{ "unlimited": true }"#).unwrap();
+        assert_eq!(cfg.text_limit, None);
+
+        assert!(serde_json::from_str::<PartsConfig>(r#"{ "allowed_mime_types": ["x"] }"#).is_err());
+        assert!(serde_json::from_str::<PartsConfig>(r#"{ "no_such_key": 1 }"#).is_err());
+        assert!(serde_json::from_str::<PartsConfig>(r#"{ "file_limit": "lots" }"#).is_err());
+    }
+
+    #[test]
+    pub fn later_options_are_deserialized() {
+        let cfg: PartsConfig = serde_json::from_str(
+            r#"{
+                "temp_file_mode": 384,
+                "temp_dir_for": [["video", "/mnt/big"]]
+            }"#,
+        )
+        .expect("Failed deserializing config");
+        assert_eq!(
+            cfg.field_temp_dirs.as_deref(),
+            Some(&[("video".to_string(), PathBuf::from("/mnt/big"))][..])
+        );
+        #[cfg(unix)]
+        assert_eq!(cfg.temp_file_mode, Some(0o600));
+
+        #[cfg(not(feature = "v1"))]
+        {
+            let cfg: PartsConfig = serde_json::from_str(
+                r#"{
+                    "deadline": "2m",
+                    "field_timeout": 30,
+                    "min_transfer_rate": ["1KB", "500ms"],
+                    "max_transfer_rate": "1MB"
+                }"#,
+            )
+            .expect("Failed deserializing config");
+            assert_eq!(cfg.deadline, Some(Duration::from_secs(120)));
+            assert_eq!(cfg.field_timeout, Some(Duration::from_secs(30)));
+            assert_eq!(cfg.min_transfer_rate, Some((1000, Duration::from_millis(500))));
+            assert_eq!(cfg.max_transfer_rate, Some(1_000_000));
+
+            assert!(serde_json::from_str::<PartsConfig>(r#"{ "deadline": "soon" }"#).is_err());
+            assert!(serde_json::from_str::<PartsConfig>(r#"{ "deadline": -1 }"#).is_err());
+        }
+    }
+}
@@ -4,13 +4,66 @@ A convenience library for working with multipart/form-data in [`actix-web`](http
 This library uses [`actix-multipart`](https://docs.rs/actix-multipart) internally, and is not a replacement
 for `actix-multipart`. It saves multipart file data to tempfiles and collects text data, handling all blocking I/O operations.
 
-Provides some configuration options in [PartsConfig](struct.PartsConfig.html):
+Fields are read one at a time, in the order they arrive, so at most one part per request is being
+buffered or written at any moment.
+
+Provides some configuration options in [PartsConfig](struct.PartsConfig.html). By default,
+including when none is registered, file fields are limited to 10 MB, text fields to 64 KB and
+requests to 20 files (see `PartsConfig::recommended()`); start from `PartsConfig::unlimited()` to
+opt out.
 
 * **text_limit**: Any text field data larger than this number of bytes will be saved as a tempfile
 * **file_limit**: Any file field data larger than this number of bytes will be discarded/ignored
 * **file_fields**: Treat fields with these names as file fields
 * **text_fields**: Treat fields with these names as text fields
 * **temp_dir**: Use this folder as the tmp directory, rather than `tempfile`'s default
+* **tiered_temp_dirs**: Move file data larger than the given thresholds to other tmp directories
+* **total_limit**: Any request with more than this number of bytes of field data will be rejected, as will any whose `Content-Length` (which also counts boundaries and part headers) is over it
+* **max_files**: Any request with more than this number of file fields will be rejected
+* **max_fields**: Any request with more than this number of fields (text and file) will be rejected, not counting dropped ones
+* **max_field_name_len** / **max_file_name_len**: Any longer names will be truncated or rejected, per **long_name_policy**
+* **field_router**: Hand fields with these names to async handlers as soon as they have been read (not available with `actix-web` 1.x)
+* **signature**: Reject requests whose fields do not match an HMAC signature sent with them (requires the `signature` feature)
+* **file_field_pattern** / **text_field_pattern**: Treat fields with names matching these regular expressions as file or text fields (requires the `regex` feature)
+* **content_type_classification** / **file_content_types**: Treat fields sent with a non-`text/plain` (or one of the given) `Content-Type` as file fields, even without a filename
+* **in_memory_only**: Keep file field data in memory rather than tempfiles, never touching the filesystem, and reject any request with more than this number of bytes of field data, or with a larger `Content-Length`
+* **allowed_mime_types** / **denied_mime_types**: Drain file fields with other (or these) declared content types without storing them, recording an error or rejecting the request per **disallowed_file_policy**
+* **file_mix**: Reject any request whose file fields (by declared content type) fit none of the given alternatives, e.g. "at most 10 images OR 1 video"
+* **allowed_extensions**: Drain file fields whose sanitized filename has another extension without storing them, per **disallowed_file_policy**
+* **required_fields**: Fail any request missing one of these fields with 400 Bad Request, listing the missing names
+* **sensitive_fields**: Print `«redacted»` instead of the values of these text fields in `Debug` output
+* **duplicate_policy**: Keep the first, last or all fields sharing a name, or reject the request
+* **error_mode**: Fail the request on the first failed part, or keep reading and collect part errors in `Parts`
+* **error_handler**: Turn extraction errors into your own responses, like `JsonConfig::error_handler`
+* **oversize_policy**: Reject the request, skip the file (the default) or truncate it when file field data is larger than **file_limit**
+* **text_overflow_policy**: Fail the request with 400 Bad Request, rather than saving a tempfile, when text field data is larger than **text_limit**
+* **temp_file_prefix** / **temp_file_suffix**: Name tempfiles with this prefix and suffix, so they can be identified in shared tmp directories
+* **temp_file_mode**: Create tempfiles with these permissions on Unix, e.g. `0o600`
+* **memory_threshold**: Keep file field data in memory until it is larger than this number of bytes, only then saving it as a tempfile
+* **discard_files**: Drain file fields without storing them, keeping only their names, content types and sizes
+* **skip_empty_files**: Drop file fields sent with no file name and no contents, as browsers do for empty file inputs
+* **strict_content_types**: Fail parts whose `Content-Type` header cannot be parsed, rather than ignoring the header
+* **trim_text**: Strip leading and trailing whitespace from text fields
+* **strip_bom**: Strip a leading UTF-8 byte order mark from text fields
+* **normalize_newlines**: Convert CRLF line endings in text fields to LF
+* **strict_utf8**: Fail with 400 when a text field is not valid UTF-8, rather than leaving it out of `as_pairs` and `to_query_string`
+* **case_insensitive_fields**: Lowercase field names as they are collected and match configured field names without regard to case
+* **normalize_array_fields**: Collect `photos[]` and `photos[0]` style fields under their base name
+* **field_filter**: Decide per field whether it is accepted, discarded, or forced to be a text or file
+* **only_fields**: Drain any field not listed here without buffering it
+* **text_charset_decoding**: Transcode text fields in other declared charsets to UTF-8 (requires the `encoding_rs` feature)
+* **base64_text_decoding**: Decode text fields sent with `Content-Transfer-Encoding: base64` (requires the `base64` feature)
+* **create_temp_dir**: Create missing tmp directories (with optional Unix permissions) on first use
+* **temp_dir_for**: Spool fields with particular names to their own tmp directories
+* **deadline**: Fail with 408 if the whole body has not been read in time (not available with `actix-web` 1.x)
+* **field_timeout**: Fail with 408 if a field stalls between chunks, or the body between fields, for this long (not available with `actix-web` 1.x)
+* **min_transfer_rate**: Fail with 408 if the body arrives more slowly than this after a grace period (not available with `actix-web` 1.x)
+* **max_transfer_rate**: Pace reads so the body is read no faster than this (not available with `actix-web` 1.x)
+* **max_part_header_size**: Reject requests with fields whose headers add up to more than this many bytes
+* **total_text_limit**: Reject requests whose text fields add up to more than this many bytes in memory
+* **min_free_space**: Fail with 507 rather than leave less than this much free space for tempfiles (requires the `fs2` feature)
+* **temp_quota**: Reject requests whose tempfiles add up to more than this many bytes
+* **max_parts**: Stop reading after this many parts, including dropped ones
 
 # Usage
 
@@ -19,9 +72,50 @@ This crate supports both major versions of `actix-web`, 1.x, 2.x, 3.x, and 4.x.
 To use with `actix-web` 1.x, add the following to your `Cargo.toml`:
 
 ```toml
-awmp = { version = "0.8", default-features = false, features = ["v1"] }
+awmp = { version = "0.9", default-features = false, features = ["v1"] }
 ```
 
+The `low-level` feature exposes the building blocks used by the `Parts` extractor (see
+[low_level](low_level/index.html)), for composing your own extractors.
+
+The `recipes` feature adds helpers for common handler patterns (see [recipes](recipes/index.html)).
+
+The `signature` feature verifies HMAC-signed submissions inside the extractor (see
+[signature](signature/index.html)).
+
+The `regex` feature adds `PartsConfig::with_file_field_pattern` and
+`PartsConfig::with_text_field_pattern`, for classifying fields by regular expression.
+
+The `encoding_rs` feature adds `PartsConfig::with_text_charset_decoding`, for transcoding text
+fields sent in other charsets to UTF-8.
+
+The `base64` feature adds `PartsConfig::with_base64_text_decoding`, for clients that base64
+encode text fields.
+
+The `tokio` feature adds `File::into_async_file` and `File::into_async_read`, for streaming
+uploads onward from async handlers, and `File::persist_in_async` and `File::persist_at_async`,
+for persisting them without blocking the executor. It requires the `v4` feature, as older
+actix-web versions run on tokio 0.2.
+
+The `infer` feature adds `File::detected_content_type`, for checking uploads by their contents
+rather than the content type the client declared.
+
+The `digest` feature adds `File::sha256_hex`, a checksum computed as the file is collected,
+for deduplicating or verifying uploads without reading them back.
+
+The `fs2` feature adds `PartsConfig::with_min_free_space`, for refusing uploads that would
+fill the disk.
+
+The `serde` feature implements `Deserialize` for `PartsConfig`, so it can be loaded from an
+application config file (e.g., TOML or YAML) alongside other server settings, and `Serialize`
+for `Error`, so upload errors can be included in JSON responses.
+
+Together with `serde`, the `serde_urlencoded` feature adds `TextParts::deserialize`, for mapping
+text fields onto a struct.
+
+The `problem-json` feature responds to extraction errors with RFC 7807
+`application/problem+json` bodies, including the name of the field at fault.
+
 ## Example
 
 ```rust,no_run
@@ -62,9 +156,32 @@ use bytes::Bytes;
 /// Re-export of `tempfile::NamedTempFile`
 pub use tempfile::NamedTempFile;
 
-use std::collections::HashMap;
-use std::io::{Cursor, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[cfg(not(feature = "v1"))]
+use std::time::Duration;
+
+#[cfg(feature = "low-level")]
+pub mod low_level;
+
+#[cfg(not(feature = "low-level"))]
+mod low_level;
+
+pub use low_level::FieldKind;
+
+#[cfg(feature = "recipes")]
+pub mod recipes;
+
+#[cfg(feature = "signature")]
+pub mod signature;
+
+#[cfg(feature = "serde")]
+mod de;
+
+use low_level::{classify_field, Part, RequestState};
 
 #[cfg(feature = "v1")]
 pub(crate) use actix_web_v1 as actix_web;
@@ -105,12 +222,45 @@ pub(crate) use actix_multipart_v04 as actix_multipart;
 #[path = "v4.rs"]
 pub mod v4;
 
+#[cfg(all(feature = "tokio", any(feature = "v1", feature = "v2", feature = "v3")))]
+compile_error!("the `tokio` feature requires the `v4` feature (actix-web 4 runs on tokio 1)");
+
 /// Error container
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
     TempFilePersistError(tempfile::PersistError),
-    FileTooLarge { limit: usize, file_name: Option<String> },
+    FileTooLarge { field: String, limit: usize, received: usize, file_name: Option<String> },
+    RequestTooLarge { limit: usize },
+    TooManyFiles { limit: usize },
+    TooManyFields { limit: usize },
+    TooManyParts { limit: usize },
+    NameTooLong { limit: usize },
+    InvalidSignature,
+    DisallowedMimeType { field: String, mime_type: String, file_name: Option<String> },
+    FileMixNotAllowed,
+    DisallowedExtension { field: String, extension: Option<String>, file_name: Option<String> },
+    MissingFields { fields: Vec<String> },
+    DuplicateField { name: String },
+    TextTooLarge { limit: usize, name: String },
+    InvalidSize { value: String },
+    InvalidConfig { reason: String },
+    DeadlineExceeded,
+    FieldTimeout { name: Option<String> },
+    TransferTooSlow { rate: usize },
+    HeadersTooLarge { limit: usize },
+    TextsTooLarge { limit: usize },
+    InsufficientStorage { min_free_space: usize },
+    TempQuotaExceeded { limit: usize },
+    MultipartError { field: Option<String>, source: actix_multipart::MultipartError },
+    InvalidContentType { field: String, got: String },
+    NotMultipart { content_type: Option<String> },
+    MissingBoundary,
+    MissingFieldName { index: usize },
+    StorageExhausted { source: std::io::Error },
+    InvalidUtf8 { field: String },
+    InvalidValue { field: String, reason: String },
+    InvalidForm { reason: String },
 }
 
 impl std::fmt::Display for Error {
@@ -118,13 +268,96 @@ impl std::fmt::Display for Error {
         match self {
             Error::Io(ref x) => x.fmt(f),
             Error::TempFilePersistError(ref x) => x.fmt(f),
-            Error::FileTooLarge { limit, ref file_name } => {
+            Error::FileTooLarge { ref field, limit, received, ref file_name } => {
+                write!(
+                    f,
+                    "File is too large ({} bytes, limit: {} bytes): {}",
+                    received, limit, field
+                )?;
                 if let Some(ref file_name) = file_name {
-                    write!(f, "File is too large (limit: {} bytes): {}", limit, file_name)
+                    write!(f, " ({})", file_name)?;
+                }
+                Ok(())
+            }
+            Error::RequestTooLarge { limit } => {
+                write!(f, "Request is too large (limit: {} bytes)", limit)
+            }
+            Error::TooManyFiles { limit } => write!(f, "Too many files (limit: {})", limit),
+            Error::TooManyFields { limit } => write!(f, "Too many fields (limit: {})", limit),
+            Error::TooManyParts { limit } => write!(f, "Too many parts (limit: {})", limit),
+            Error::NameTooLong { limit } => {
+                write!(f, "Field name or filename is too long (limit: {} bytes)", limit)
+            }
+            Error::InvalidSignature => write!(f, "Signature is missing or invalid"),
+            Error::DeadlineExceeded => write!(f, "Request was not read within the deadline"),
+            Error::FieldTimeout { name: Some(ref name) } => {
+                write!(f, "Field stalled while reading: {}", name)
+            }
+            Error::FieldTimeout { name: None } => write!(f, "Request stalled between fields"),
+            Error::TempQuotaExceeded { limit } => {
+                write!(f, "Tempfiles for the request are too large (limit: {} bytes)", limit)
+            }
+            Error::InvalidUtf8 { ref field } => {
+                write!(f, "Text field is not valid UTF-8: {}", field)
+            }
+            Error::InvalidValue { ref field, ref reason } => {
+                write!(f, "Invalid value for field {}: {}", field, reason)
+            }
+            Error::InvalidForm { ref reason } => write!(f, "Invalid form: {}", reason),
+            Error::StorageExhausted { ref source } => {
+                write!(f, "Ran out of space for tempfiles: {}", source)
+            }
+            Error::InsufficientStorage { min_free_space } => {
+                write!(f, "Not enough free space for tempfiles (minimum: {} bytes)", min_free_space)
+            }
+            Error::TextsTooLarge { limit } => {
+                write!(f, "Text fields are too large together (limit: {} bytes)", limit)
+            }
+            Error::HeadersTooLarge { limit } => {
+                write!(f, "Field headers are too large (limit: {} bytes)", limit)
+            }
+            Error::TransferTooSlow { rate } => {
+                write!(f, "Request was sent too slowly (minimum: {} bytes per second)", rate)
+            }
+            Error::FileMixNotAllowed => write!(f, "Request contains a disallowed mix of files"),
+            Error::TextTooLarge { limit, ref name } => {
+                write!(f, "Text field is too large (limit: {} bytes): {}", limit, name)
+            }
+            Error::InvalidSize { ref value } => write!(f, "Invalid size: {}", value),
+            Error::InvalidConfig { ref reason } => write!(f, "Invalid configuration: {}", reason),
+            Error::DuplicateField { ref name } => {
+                write!(f, "Field was sent more than once: {}", name)
+            }
+            Error::MissingFields { ref fields } => {
+                write!(f, "Missing required fields: {}", fields.join(", "))
+            }
+            Error::DisallowedExtension { ref field, ref extension, ref file_name } => {
+                let extension = extension.as_deref().unwrap_or("none");
+                let file_name = file_name.as_deref().unwrap_or(field);
+                write!(f, "File extension is not allowed ({}): {}", extension, file_name)
+            }
+            Error::DisallowedMimeType { ref field, ref mime_type, ref file_name } => {
+                let file_name = file_name.as_deref().unwrap_or(field);
+                write!(f, "File type is not allowed ({}): {}", mime_type, file_name)
+            }
+            Error::MultipartError { ref field, ref source } => {
+                if let Some(ref field) = field {
+                    write!(f, "Invalid multipart data in field {}: {}", field, source)
                 } else {
-                    write!(f, "File is too large (limit: {} bytes)", limit)
+                    write!(f, "Invalid multipart data: {}", source)
                 }
             }
+            Error::InvalidContentType { ref field, ref got } => {
+                write!(f, "Field has an invalid content type ({}): {}", got, field)
+            }
+            Error::NotMultipart { ref content_type } => {
+                let content_type = content_type.as_deref().unwrap_or("none");
+                write!(f, "Request is not multipart/form-data ({})", content_type)
+            }
+            Error::MissingBoundary => write!(f, "Multipart request has no boundary"),
+            Error::MissingFieldName { index } => {
+                write!(f, "Field name is required (part index: {})", index)
+            }
         }
     }
 }
@@ -134,9 +367,165 @@ impl std::error::Error for Error {
         match self {
             Error::Io(ref x) => Some(x),
             Error::TempFilePersistError(ref x) => Some(x),
+            Error::StorageExhausted { ref source } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// A short name for the kind of error, e.g. `"file_too_large"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io",
+            Error::TempFilePersistError(_) => "temp_file_persist",
+            Error::FileTooLarge { .. } => "file_too_large",
+            Error::RequestTooLarge { .. } => "request_too_large",
+            Error::TooManyFiles { .. } => "too_many_files",
+            Error::TooManyFields { .. } => "too_many_fields",
+            Error::TooManyParts { .. } => "too_many_parts",
+            Error::NameTooLong { .. } => "name_too_long",
+            Error::InvalidSignature => "invalid_signature",
+            Error::DisallowedMimeType { .. } => "disallowed_mime_type",
+            Error::FileMixNotAllowed => "file_mix_not_allowed",
+            Error::DisallowedExtension { .. } => "disallowed_extension",
+            Error::MissingFields { .. } => "missing_fields",
+            Error::DuplicateField { .. } => "duplicate_field",
+            Error::TextTooLarge { .. } => "text_too_large",
+            Error::InvalidSize { .. } => "invalid_size",
+            Error::InvalidConfig { .. } => "invalid_config",
+            Error::DeadlineExceeded => "deadline_exceeded",
+            Error::FieldTimeout { .. } => "field_timeout",
+            Error::TransferTooSlow { .. } => "transfer_too_slow",
+            Error::HeadersTooLarge { .. } => "headers_too_large",
+            Error::TextsTooLarge { .. } => "texts_too_large",
+            Error::InsufficientStorage { .. } => "insufficient_storage",
+            Error::TempQuotaExceeded { .. } => "temp_quota_exceeded",
+            Error::MultipartError { .. } => "multipart",
+            Error::InvalidContentType { .. } => "invalid_content_type",
+            Error::NotMultipart { .. } => "not_multipart",
+            Error::MissingBoundary => "missing_boundary",
+            Error::MissingFieldName { .. } => "missing_field_name",
+            Error::StorageExhausted { .. } => "storage_exhausted",
+            Error::InvalidUtf8 { .. } => "invalid_utf8",
+            Error::InvalidValue { .. } => "invalid_value",
+            Error::InvalidForm { .. } => "invalid_form",
+        }
+    }
+
+    /// The name of the field the error is about, if it is about one
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            Error::FileTooLarge { ref field, .. }
+            | Error::DisallowedMimeType { ref field, .. }
+            | Error::DisallowedExtension { ref field, .. }
+            | Error::InvalidContentType { ref field, .. }
+            | Error::InvalidUtf8 { ref field }
+            | Error::InvalidValue { ref field, .. } => Some(field),
+            Error::DuplicateField { ref name } | Error::TextTooLarge { ref name, .. } => Some(name),
+            Error::MultipartError { ref field, .. } => field.as_deref(),
+            Error::FieldTimeout { ref name } => name.as_deref(),
             _ => None,
         }
     }
+
+    /// The error as an RFC 7807 problem document, with the field name (if any) as `field`
+    #[cfg(feature = "problem-json")]
+    pub fn to_problem_json(&self) -> serde_json::Value {
+        let status = self.status_code();
+
+        let mut problem = serde_json::json!({
+            "type": format!("urn:awmp:error:{}", self.kind()),
+            "title": status.canonical_reason().unwrap_or("Error"),
+            "status": status.as_u16(),
+            "detail": self.to_string(),
+        });
+
+        if let Some(field) = self.field() {
+            problem["field"] = field.into();
+        }
+
+        problem
+    }
+
+    /// The HTTP status for responding with this error
+    pub fn status_code(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+
+        match self {
+            Error::Io(_)
+            | Error::TempFilePersistError(_)
+            | Error::InvalidSize { .. }
+            | Error::InvalidConfig { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::FileTooLarge { .. }
+            | Error::RequestTooLarge { .. }
+            | Error::TooManyFiles { .. }
+            | Error::TooManyFields { .. }
+            | Error::TooManyParts { .. }
+            | Error::TextsTooLarge { .. }
+            | Error::TempQuotaExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::NameTooLong { .. }
+            | Error::FileMixNotAllowed
+            | Error::MissingFields { .. }
+            | Error::DuplicateField { .. }
+            | Error::TextTooLarge { .. }
+            | Error::HeadersTooLarge { .. }
+            | Error::MultipartError { .. }
+            | Error::InvalidContentType { .. }
+            | Error::NotMultipart { .. }
+            | Error::MissingBoundary
+            | Error::MissingFieldName { .. }
+            | Error::InvalidUtf8 { .. }
+            | Error::InvalidValue { .. } => StatusCode::BAD_REQUEST,
+            Error::InvalidSignature => StatusCode::UNAUTHORIZED,
+            Error::DisallowedMimeType { .. } | Error::DisallowedExtension { .. } => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            Error::DeadlineExceeded
+            | Error::FieldTimeout { .. }
+            | Error::TransferTooSlow { .. } => StatusCode::REQUEST_TIMEOUT,
+            Error::InvalidForm { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::InsufficientStorage { .. } | Error::StorageExhausted { .. } => {
+                StatusCode::INSUFFICIENT_STORAGE
+            }
+        }
+    }
+}
+
+/// Serializes as `kind`, `message`, `status` and `field` (or `null`), e.g. for JSON error bodies
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Error", 4)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("status", &self.status_code().as_u16())?;
+        state.serialize_field("field", &self.field())?;
+        state.end()
+    }
+}
+
+/// Lets handlers return `awmp::Error` with `?`, responding with `Error::status_code` (and a
+/// problem document with the `problem-json` feature)
+impl actix_web::ResponseError for Error {
+    #[cfg(not(feature = "v1"))]
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        Error::status_code(self)
+    }
+
+    #[cfg(feature = "problem-json")]
+    fn error_response(&self) -> actix_web::HttpResponse {
+        actix_web::HttpResponse::build(Error::status_code(self))
+            .content_type("application/problem+json")
+            .body(self.to_problem_json().to_string())
+    }
+
+    #[cfg(all(feature = "v1", not(feature = "problem-json")))]
+    fn error_response(&self) -> actix_web::HttpResponse {
+        actix_web::HttpResponse::new(Error::status_code(self))
+    }
 }
 
 /// The parts of a multipart/form-data request
@@ -144,36 +533,188 @@ impl std::error::Error for Error {
 pub struct Parts {
     pub texts: TextParts,
     pub files: FileParts,
+    /// File fields that were drained without being stored (see
+    /// `PartsConfig::with_discard_files`), in arrival order
+    pub discarded_files: Vec<(String, FileMetadata)>,
+    /// Text and other non-file fields that failed under `ErrorMode::Collect`, in arrival order
+    pub failed: Vec<(String, Error)>,
+}
+
+/// What was sent for a file field drained under `PartsConfig::with_discard_files`
+#[derive(Debug, Clone)]
+pub struct FileMetadata {
+    pub file_name: Option<String>,
+    pub content_type: mime::Mime,
+    pub size: usize,
 }
 
 /// The text parts of a multipart/form-data request
-#[derive(Debug)]
-pub struct TextParts(Vec<(String, Bytes)>);
+///
+/// Parts are kept in the order they arrived in the request, unless reordered with `sort_by_name`.
+/// Each part also remembers its position in the request (see `iter_indexed`) and its declared
+/// `Content-Type` (see `content_type`).
+pub struct TextParts(Vec<(String, Bytes)>, Vec<TextMeta>);
+
+/// What is kept about a text part besides its name and value
+#[derive(Debug, Clone)]
+pub(crate) struct TextMeta {
+    pub(crate) index: usize,
+    pub(crate) content_type: Option<mime::Mime>,
+    pub(crate) sensitive: bool,
+}
+
+impl std::fmt::Debug for TextParts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Redacted;
+
+        impl std::fmt::Debug for Redacted {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("«redacted»")
+            }
+        }
+
+        let entries = self.0.iter().zip(self.1.iter()).map(|((k, v), meta)| {
+            let v: &dyn std::fmt::Debug = if meta.sensitive { &Redacted } else { v };
+            (k, v)
+        });
+        f.debug_tuple("TextParts").field(&entries.collect::<Vec<_>>()).finish()
+    }
+}
 
 /// The file parts of a multipart/form-data request
+///
+/// Parts are kept in the order they arrived in the request, unless reordered with `sort_by_name`.
+/// Each part also remembers its position in the request (see `iter_indexed`).
 #[derive(Debug)]
-pub struct FileParts(Vec<(String, Result<File, Error>)>);
+pub struct FileParts(Vec<(String, Result<File, Error>)>, Vec<usize>);
+
+impl From<Vec<(String, Bytes)>> for TextParts {
+    /// Wrap the given parts, numbering them in order
+    fn from(parts: Vec<(String, Bytes)>) -> Self {
+        let meta = (0..parts.len())
+            .map(|index| TextMeta { index, content_type: None, sensitive: false })
+            .collect();
+        TextParts(parts, meta)
+    }
+}
+
+impl From<Vec<(String, Result<File, Error>)>> for FileParts {
+    /// Wrap the given parts, numbering them in order
+    fn from(parts: Vec<(String, Result<File, Error>)>) -> Self {
+        let indexes = (0..parts.len()).collect();
+        FileParts(parts, indexes)
+    }
+}
+
+impl IntoIterator for FileParts {
+    type Item = (String, Result<File, Error>);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a FileParts {
+    type Item = &'a (String, Result<File, Error>);
+    type IntoIter = std::slice::Iter<'a, (String, Result<File, Error>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
 
 /// A tempfile wrapper that includes the original filename
+///
+/// With `PartsConfig::with_in_memory_only` (or for small files, `with_memory_threshold`), the
+/// contents are held in memory instead of a tempfile (see `File::bytes`).
 #[derive(Debug)]
 pub struct File {
-    inner: NamedTempFile,
+    inner: FileData,
     original_file_name: Option<String>,
     sanitized_file_name: String,
+    size: Option<u64>,
+    content_type: mime::Mime,
+    headers: Vec<(String, Bytes)>,
+    #[cfg_attr(not(feature = "infer"), allow(dead_code))]
+    detected_content_type: Option<mime::Mime>,
+    #[cfg_attr(not(feature = "digest"), allow(dead_code))]
+    sha256: Option<[u8; 32]>,
+    /// Read position for in-memory contents (see the `Read` impl)
+    memory_pos: u64,
+}
+
+#[derive(Debug)]
+enum FileData {
+    Disk(NamedTempFile),
+    Memory(Bytes),
+}
+
+/// Reads from the tempfile or from memory, so a `&mut File` can be passed straight to parsers
+///
+/// Files collected from a request start out at the beginning of their contents.
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.inner {
+            FileData::Disk(ref mut file) => file.read(buf),
+            FileData::Memory(ref bytes) => {
+                let mut cursor = Cursor::new(bytes.as_ref());
+                cursor.set_position(self.memory_pos);
+                let n = cursor.read(buf)?;
+                self.memory_pos = cursor.position();
+                Ok(n)
+            }
+        }
+    }
 }
 
-impl AsRef<NamedTempFile> for File {
-    fn as_ref(&self) -> &NamedTempFile {
-        &self.inner
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self.inner {
+            FileData::Disk(ref mut file) => file.seek(pos),
+            FileData::Memory(ref bytes) => {
+                let mut cursor = Cursor::new(bytes.as_ref());
+                cursor.set_position(self.memory_pos);
+                self.memory_pos = cursor.seek(pos)?;
+                Ok(self.memory_pos)
+            }
+        }
     }
 }
 
-impl AsMut<NamedTempFile> for File {
-    fn as_mut(&mut self) -> &mut NamedTempFile {
-        &mut self.inner
+impl Parts {
+    /// The number of text and file parts (including parts that failed or were discarded)
+    pub fn len(&self) -> usize {
+        self.texts.0.len() + self.files.0.len() + self.discarded_files.len() + self.failed.len()
+    }
+
+    /// Whether the request had no usable fields at all
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `HashMap` of field names and how they were collected
+    /// NOTE: a name used for both text and file parts is reported as `FieldKind::File`
+    pub fn field_kinds(&self) -> HashMap<&str, FieldKind> {
+        let texts = self.texts.0.iter().map(|(k, _)| (k.as_str(), FieldKind::Text));
+        let files = self.files.0.iter().map(|(k, _)| (k.as_str(), FieldKind::File));
+        let discarded = self.discarded_files.iter().map(|(k, _)| (k.as_str(), FieldKind::File));
+        texts.chain(files).chain(discarded).collect()
+    }
+
+    /// Returns the field names and errors of parts that failed without failing the request
+    /// (e.g., files over the limit with `OversizePolicy::SkipPart`), files first, each in
+    /// arrival order
+    pub fn errors(&self) -> Vec<(&str, &Error)> {
+        let mut errors = self.files.errors();
+        errors.extend(self.failed.iter().map(|(k, e)| (k.as_str(), e)));
+        errors
     }
 }
 
+/// Values treated as `true` by `TextParts::get_bool`
+pub const DEFAULT_TRUTHY_VALUES: &[&str] = &["on", "true", "1"];
+
 impl TextParts {
     pub fn into_inner(self) -> Vec<(String, Bytes)> {
         self.0
@@ -188,6 +729,119 @@ impl TextParts {
             .collect()
     }
 
+    /// Like `as_pairs`, but keeps non-UTF8 values, with invalid sequences replaced by `U+FFFD`
+    pub fn as_pairs_lossy(&self) -> Vec<(&str, std::borrow::Cow<'_, str>)> {
+        self.0.iter().map(|(key, val)| (key.as_str(), String::from_utf8_lossy(val))).collect()
+    }
+
+    /// Get the first value for given name, decoded from the given encoding regardless of any
+    /// declared charset or byte order mark (requires the `encoding_rs` feature)
+    ///
+    /// Malformed sequences are replaced by `U+FFFD`.
+    #[cfg(feature = "encoding_rs")]
+    pub fn decode_as(
+        &self,
+        key: &str,
+        encoding: &'static encoding_rs::Encoding,
+    ) -> Option<std::borrow::Cow<'_, str>> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.as_str() == key)
+            .map(|(_, val)| encoding.decode_without_bom_handling(val).0)
+    }
+
+    /// Get the first value for given name, or `None` if it is not valid UTF-8
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.as_str() == key)
+            .and_then(|(_, val)| std::str::from_utf8(val).ok())
+    }
+
+    /// Get all values for given name, in arrival order, leaving out any that are non-UTF8
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(k, _)| k.as_str() == key)
+            .flat_map(|(_, val)| std::str::from_utf8(val))
+            .collect()
+    }
+
+    /// Parse the first value for given name, failing with `Error::MissingFields` if there is
+    /// none (or it is non-UTF8) and `Error::InvalidValue` if it does not parse
+    pub fn parse<T>(&self, key: &str) -> Result<T, Error>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let val = self.get(key).ok_or_else(|| Error::MissingFields { fields: vec![key.into()] })?;
+        val.parse()
+            .map_err(|e: T::Err| Error::InvalidValue { field: key.into(), reason: e.to_string() })
+    }
+
+    /// Interpret the first value for given name as a checkbox, i.e. `true` if it is one of
+    /// `DEFAULT_TRUTHY_VALUES` (ignoring case and surrounding whitespace) and `false` otherwise,
+    /// including when the field is missing
+    pub fn get_bool(&self, key: &str) -> bool {
+        self.get_bool_with(key, DEFAULT_TRUTHY_VALUES)
+    }
+
+    /// Like `get_bool`, but with a custom set of values that count as `true`
+    pub fn get_bool_with(&self, key: &str, truthy: &[&str]) -> bool {
+        self.get(key)
+            .map(|val| truthy.iter().any(|x| x.eq_ignore_ascii_case(val.trim())))
+            .unwrap_or(false)
+    }
+
+    /// Get the `Content-Type` declared for the first value for given name, if any
+    pub fn content_type(&self, key: &str) -> Option<&mime::Mime> {
+        self.0
+            .iter()
+            .zip(self.1.iter())
+            .find(|((k, _), _)| k.as_str() == key)
+            .and_then(|(_, meta)| meta.content_type.as_ref())
+    }
+
+    /// Mark the values of `sensitive_fields` to be redacted in `Debug` output
+    ///
+    /// The extractors do this for the parts they collect. Call it for text parts built some other
+    /// way, e.g., with `TextParts::from` or by collecting `Part`s from `read_field`.
+    pub fn mark_sensitive(&mut self, cfg: &PartsConfig) {
+        for ((k, _), meta) in self.0.iter().zip(self.1.iter_mut()) {
+            meta.sensitive = cfg.lists_field(cfg.sensitive_fields.as_ref(), k);
+        }
+    }
+
+    /// Returns all values for the given name and removes them from the container
+    pub fn take(&mut self, key: &str) -> Vec<Bytes> {
+        let mut taken = Vec::with_capacity(self.0.len());
+        let mut untaken = Vec::with_capacity(self.0.len());
+        let mut untaken_meta = Vec::with_capacity(self.1.len());
+
+        for (meta, (k, v)) in self.1.drain(..).zip(self.0.drain(..)) {
+            if k == key {
+                taken.push(v);
+            } else {
+                untaken.push((k, v));
+                untaken_meta.push(meta);
+            }
+        }
+
+        self.0 = untaken;
+        self.1 = untaken_meta;
+
+        taken
+    }
+
+    /// Iterates over the parts along with their position in the request
+    ///
+    /// The position counts every collected field, text or not, in the order it arrived, so
+    /// together with `FileParts::iter_indexed` the original order of a mixed form can be
+    /// reconstructed, even after `take` or `sort_by_name`.
+    pub fn iter_indexed(&self) -> impl Iterator<Item = (usize, &str, &Bytes)> {
+        self.1.iter().zip(self.0.iter()).map(|(meta, (k, v))| (meta.index, k.as_str(), v))
+    }
+
     /// Re-encodes the string-like text parts to a query string
     pub fn to_query_string(&self) -> String {
         let mut qs = url::form_urlencoded::Serializer::new(String::new());
@@ -204,10 +858,69 @@ impl TextParts {
     }
 
     /// Returns `HashMap`  of field names and values
-    /// NOTE: this will discard the first of multiple values for a key
+    /// NOTE: this will discard the first of multiple values for a key (see `as_multi_map`)
     pub fn as_hash_map(&self) -> HashMap<&str, &str> {
         self.as_pairs().into_iter().collect()
     }
+
+    /// Returns `HashMap` of field names and all their values as `&str`s, in arrival order,
+    /// leaving out non-UTF8 values
+    pub fn as_multi_map(&self) -> HashMap<&str, Vec<&str>> {
+        let mut map = HashMap::new();
+        for (key, val) in self.as_pairs() {
+            map.entry(key).or_insert_with(Vec::new).push(val);
+        }
+        map
+    }
+
+    /// Like `as_multi_map`, but consumes the container and returns owned strings
+    pub fn into_multi_map(self) -> HashMap<String, Vec<String>> {
+        let mut map = HashMap::new();
+        for (key, val) in self.0 {
+            if let Ok(val) = String::from_utf8(val.to_vec()) {
+                map.entry(key).or_insert_with(Vec::new).push(val);
+            }
+        }
+        map
+    }
+
+    /// Sorts the parts by field name, keeping values for the same name in arrival order
+    pub fn sort_by_name(&mut self) {
+        sort_indexed_by_name(&mut self.0, &mut self.1);
+    }
+
+    /// Builds a nested JSON object from bracketed field names, e.g. `user[name]`, `tags[0]` or
+    /// `tags[]` (requires the `serde_json` feature)
+    ///
+    /// Numeric and empty brackets index into arrays (an index past the end appends), other
+    /// brackets into objects. A name repeated at the same place collects its values into an
+    /// array. Non-UTF8 values and values that conflict with the shape built so far are left out.
+    #[cfg(feature = "serde_json")]
+    pub fn to_json_value(&self) -> serde_json::Value {
+        let mut root = serde_json::Value::Object(Default::default());
+        for (key, val) in self.as_pairs() {
+            insert_json_value(&mut root, &json_key_path(key), val);
+        }
+        root
+    }
+
+    /// Deserialize the string-like text parts into a struct, failing with `Error::InvalidForm`
+    /// (422 Unprocessable Entity) if they do not fit (requires the `serde` and
+    /// `serde_urlencoded` features)
+    #[cfg(all(feature = "serde", feature = "serde_urlencoded"))]
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self) -> Result<T, Error> {
+        serde_urlencoded::from_str(&self.to_query_string())
+            .map_err(|e| Error::InvalidForm { reason: e.to_string() })
+    }
+
+    /// Returns `BTreeMap` of field names and all their values, in arrival order
+    pub fn grouped_by_name(&self) -> BTreeMap<&str, Vec<&Bytes>> {
+        let mut map = BTreeMap::new();
+        for (key, val) in self.0.iter() {
+            map.entry(key.as_str()).or_insert_with(Vec::new).push(val);
+        }
+        map
+    }
 }
 
 impl FileParts {
@@ -215,11 +928,76 @@ impl FileParts {
         self.0
     }
 
+    /// The number of non-error files
+    pub fn len(&self) -> usize {
+        self.0.iter().filter(|(_, v)| v.is_ok()).count()
+    }
+
+    /// The number of file parts, including ones that failed (see `errors`)
+    pub fn len_with_errors(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether there are no non-error files
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The field names with non-error files, each once, in arrival order
+    pub fn keys(&self) -> Vec<&str> {
+        unique_keys(self.0.iter().filter(|(_, v)| v.is_ok()).map(|(k, _)| k.as_str()))
+    }
+
+    /// The field names of all file parts, including ones that failed, each once, in arrival
+    /// order
+    pub fn keys_with_errors(&self) -> Vec<&str> {
+        unique_keys(self.0.iter().map(|(k, _)| k.as_str()))
+    }
+
+    /// Returns the field names and errors of files that failed (e.g., files over the limit with
+    /// `OversizePolicy::SkipPart`), in arrival order
+    pub fn errors(&self) -> Vec<(&str, &Error)> {
+        self.0.iter().flat_map(|(k, v)| v.as_ref().err().map(|e| (k.as_str(), e))).collect()
+    }
+
+    /// Returns the field names and errors of files that failed and removes them from the
+    /// container
+    pub fn take_errors(&mut self) -> Vec<(String, Error)> {
+        self.extract(|_, v| v.is_err())
+            .into_iter()
+            .flat_map(|(k, v)| v.err().map(|e| (k, e)))
+            .collect()
+    }
+
     /// Get the first non-error file for given name
     pub fn first(&self, key: &str) -> Option<&File> {
         self.0.iter().filter(|(k, _)| k.as_str() == key).flat_map(|(_, v)| v.as_ref()).next()
     }
 
+    /// Get the first part for given name, whether it was saved or failed, so a missing field
+    /// can be told apart from a failed one
+    pub fn first_result(&self, key: &str) -> Option<&Result<File, Error>> {
+        self.0.iter().find(|(k, _)| k.as_str() == key).map(|(_, v)| v)
+    }
+
+    /// Returns all parts for the given name, including failed ones, and removes them from the
+    /// container
+    pub fn take_result(&mut self, key: &str) -> Vec<Result<File, Error>> {
+        self.extract(|k, _| k == key).into_iter().map(|(_, v)| v).collect()
+    }
+
+    /// Get the first non-error file for given name, mutably (e.g., to rewrite the tempfile
+    /// through `File::as_temp_file_mut`)
+    pub fn first_mut(&mut self, key: &str) -> Option<&mut File> {
+        self.0.iter_mut().filter(|(k, _)| k.as_str() == key).flat_map(|(_, v)| v.as_mut()).next()
+    }
+
+    /// Get the file at the given position in the container's current order, mutably, if it did
+    /// not fail
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut File> {
+        self.0.get_mut(index).and_then(|(_, v)| v.as_mut().ok())
+    }
+
     /// Returns any files for the given name and removes them from the container
     #[deprecated(note = "Please use `take` instead")]
     pub fn remove(&mut self, key: &str) -> Vec<File> {
@@ -227,94 +1005,547 @@ impl FileParts {
     }
 
     pub fn take(&mut self, key: &str) -> Vec<File> {
-        let mut taken = Vec::with_capacity(self.0.len());
+        self.extract(|k, v| k == key && v.is_ok()).into_iter().flat_map(|(_, v)| v).collect()
+    }
+
+    /// Returns the first non-error file for the given name and removes it from the container,
+    /// leaving any others in place (unlike `take(key).pop()`, which returns the last)
+    pub fn take_first(&mut self, key: &str) -> Option<File> {
+        let pos = self.0.iter().position(|(k, v)| k == key && v.is_ok())?;
+        self.1.remove(pos);
+        self.0.remove(pos).1.ok()
+    }
+
+    /// Consumes the container and returns all non-error files grouped by field name, in
+    /// arrival order
+    pub fn take_all(self) -> HashMap<String, Vec<File>> {
+        let mut map = HashMap::new();
+        for (key, val) in self.0 {
+            if let Ok(file) = val {
+                map.entry(key).or_insert_with(Vec::new).push(file);
+            }
+        }
+        map
+    }
+
+    /// Keeps only the non-error files for which the predicate returns `true`, dropping the
+    /// others (and deleting their tempfiles) right away. Failed parts are kept.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&str, &File) -> bool,
+    {
+        drop(self.take_if(|k, v| !f(k, v)));
+    }
+
+    /// Returns the non-error files for which the predicate returns `true`, with their field
+    /// names, and removes them from the container
+    pub fn take_if<F>(&mut self, mut f: F) -> Vec<(String, File)>
+    where
+        F: FnMut(&str, &File) -> bool,
+    {
+        self.extract(|k, v| v.as_ref().is_ok_and(|file| f(k, file)))
+            .into_iter()
+            .flat_map(|(k, v)| v.ok().map(|file| (k, file)))
+            .collect()
+    }
+
+    /// Removes the parts for which the predicate returns `true` and returns them in arrival
+    /// order, keeping the indexes of the remaining parts in step
+    fn extract<F>(&mut self, mut f: F) -> Vec<(String, Result<File, Error>)>
+    where
+        F: FnMut(&str, &Result<File, Error>) -> bool,
+    {
+        let mut taken = Vec::new();
         let mut untaken = Vec::with_capacity(self.0.len());
+        let mut untaken_indexes = Vec::with_capacity(self.1.len());
 
-        for (k, v) in self.0.drain(..) {
-            if k == key && v.is_ok() {
-                taken.push(v.unwrap());
+        for (i, (k, v)) in self.1.drain(..).zip(self.0.drain(..)) {
+            if f(&k, &v) {
+                taken.push((k, v));
             } else {
                 untaken.push((k, v));
+                untaken_indexes.push(i);
             }
         }
 
         self.0 = untaken;
+        self.1 = untaken_indexes;
 
         taken
     }
-}
 
-impl File {
-    pub fn into_inner(self) -> NamedTempFile {
-        self.inner
+    /// Persists every non-error file to an existing directory with `File::persist_in`, returning
+    /// the field names and full paths
+    ///
+    /// Stops at the first file that cannot be persisted, leaving the ones before it in place.
+    /// Files with the same sanitized name overwrite each other (see `persist_all_in_with`).
+    pub fn persist_all_in<P: AsRef<Path>>(self, dir: P) -> Result<Vec<(String, PathBuf)>, Error> {
+        self.persist_all_in_with(dir, CollisionPolicy::Overwrite)
     }
 
-    /// The filename provided in the multipart/form-data request
-    pub fn original_file_name(&self) -> Option<&str> {
-        self.original_file_name.as_deref()
+    /// Like `persist_all_in`, but with a policy for files whose sanitized name is already taken
+    /// in the directory, including by an earlier file in the same batch
+    pub fn persist_all_in_with<P: AsRef<Path>>(
+        self,
+        dir: P,
+        policy: CollisionPolicy,
+    ) -> Result<Vec<(String, PathBuf)>, Error> {
+        let dir = dir.as_ref();
+        let mut paths = Vec::with_capacity(self.0.len());
+        for (key, val) in self.0 {
+            let file = match val {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+
+            let path = file.persist_in_with(dir, policy)?;
+            paths.push((key, path));
+        }
+        Ok(paths)
     }
 
-    /// The sanitized version of the original file name, or generated name if none provided
-    pub fn sanitized_file_name(&self) -> &str {
-        &self.sanitized_file_name
+    /// The combined size in bytes of the non-error files (see `File::size`)
+    pub fn total_size(&self) -> u64 {
+        self.0.iter().flat_map(|(_, v)| v.as_ref().ok()).map(File::size).sum()
     }
 
-    #[deprecated(since = "0.5.4", note = "Please use the 'persist_in' function instead")]
-    /// Persist the tempfile to an existing directory. Uses the sanitized file name and returns
-    /// the full path
-    ///
-    /// NOTE: Because of how temporary file is stored, it cannot be persisted across filesystems.
-    /// Also neither the file contents nor the containing directory are
-    /// synchronized, so the update may not yet have reached the disk when
-    /// `persist` returns.
-    pub fn persist<P: AsRef<Path>>(self, dir: P) -> Result<PathBuf, Error> {
-        let new_path = dir.as_ref().join(&self.sanitized_file_name);
-        self.inner.persist(&new_path).map(|_| new_path).map_err(Error::TempFilePersistError)
+    /// Iterates over the parts, including ones that failed, in their current order
+    pub fn iter(&self) -> std::slice::Iter<'_, (String, Result<File, Error>)> {
+        self.0.iter()
     }
 
-    /// Persist the tempfile to an existing directory. Uses the sanitized file name and returns
-    /// the full path
-    ///
-    /// NOTE: Because of how temporary file is stored, it cannot be persisted across filesystems.
-    /// Also neither the file contents nor the containing directory are
-    /// synchronized, so the update may not yet have reached the disk when
-    /// `persist_in` returns.
-    pub fn persist_in<P: AsRef<Path>>(self, dir: P) -> Result<PathBuf, Error> {
-        let new_path = dir.as_ref().join(&self.sanitized_file_name);
-        self.inner.persist(&new_path).map(|_| new_path).map_err(Error::TempFilePersistError)
+    /// Iterates over the parts along with their position in the request (see
+    /// `TextParts::iter_indexed`)
+    pub fn iter_indexed(&self) -> impl Iterator<Item = (usize, &str, &Result<File, Error>)> {
+        self.1.iter().zip(self.0.iter()).map(|(i, (k, v))| (*i, k.as_str(), v))
     }
 
-    /// Persist the tempfile at the specified file path.
+    /// Sorts the parts by field name, keeping files for the same name in arrival order
+    pub fn sort_by_name(&mut self) {
+        sort_indexed_by_name(&mut self.0, &mut self.1);
+    }
+
+    /// Returns the non-error files sent as `prefix[0]`, `prefix[1]`, … ordered by that index
+    /// (files with the same index stay in arrival order)
+    ///
+    /// NOTE: with `PartsConfig::with_normalize_array_fields` the index is dropped as fields are
+    /// collected, so look the files up by their base name instead (e.g., with `take`).
+    pub fn grouped(&self, prefix: &str) -> Vec<&File> {
+        let mut files = self
+            .0
+            .iter()
+            .filter_map(|(k, v)| {
+                let index = k
+                    .strip_prefix(prefix)
+                    .and_then(|x| x.strip_prefix('['))
+                    .and_then(|x| x.strip_suffix(']'))
+                    .filter(|x| !x.is_empty() && x.bytes().all(|x| x.is_ascii_digit()))
+                    .and_then(|x| x.parse::<usize>().ok())?;
+                v.as_ref().ok().map(|file| (index, file))
+            })
+            .collect::<Vec<_>>();
+        files.sort_by_key(|(index, _)| *index);
+        files.into_iter().map(|(_, file)| file).collect()
+    }
+
+    /// Returns `BTreeMap` of field names and all their non-error files, in arrival order
+    pub fn grouped_by_name(&self) -> BTreeMap<&str, Vec<&File>> {
+        let mut map = BTreeMap::new();
+        for (key, val) in self.0.iter() {
+            if let Ok(file) = val {
+                map.entry(key.as_str()).or_insert_with(Vec::new).push(file);
+            }
+        }
+        map
+    }
+}
+
+impl File {
+    #[deprecated(note = "Please use `into_temp_file` instead")]
+    pub fn into_inner(self) -> Result<NamedTempFile, Error> {
+        self.into_temp_file()
+    }
+
+    /// The tempfile, or `None` if the file is held in memory (see `File::bytes`)
+    pub fn as_temp_file(&self) -> Option<&NamedTempFile> {
+        match self.inner {
+            FileData::Disk(ref file) => Some(file),
+            FileData::Memory(_) => None,
+        }
+    }
+
+    /// The tempfile, or `None` if the file is held in memory (see `File::bytes`)
+    pub fn as_temp_file_mut(&mut self) -> Option<&mut NamedTempFile> {
+        match self.inner {
+            FileData::Disk(ref mut file) => Some(file),
+            FileData::Memory(_) => None,
+        }
+    }
+
+    /// Convert into the tempfile, writing a file held in memory out to a new one (in the
+    /// default tmp directory) first
+    pub fn into_temp_file(self) -> Result<NamedTempFile, Error> {
+        match self.inner {
+            FileData::Disk(file) => Ok(file),
+            FileData::Memory(bytes) => {
+                let mut file = NamedTempFile::new().map_err(Error::Io)?;
+                file.write_all(&bytes).map_err(Error::Io)?;
+                file.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+                Ok(file)
+            }
+        }
+    }
+
+    /// Convert the tempfile into a `tokio::fs::File`, rewound to the start, for streaming the
+    /// contents onward without blocking the executor (requires the `tokio` feature)
+    ///
+    /// The tempfile's path is removed; its contents stay readable until the file is dropped.
+    /// Fails with `Error::Io` (of kind `InvalidInput`) if the file is held in memory; use
+    /// `into_async_read` to stream either kind.
+    #[cfg(feature = "tokio")]
+    pub fn into_async_file(self) -> Result<tokio::fs::File, Error> {
+        let mut file = match self.inner {
+            FileData::Disk(file) => file,
+            FileData::Memory(_) => {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "file is held in memory; use `into_async_read` instead",
+                )))
+            }
+        };
+        file.seek(SeekFrom::Start(0)).map_err(Error::Io)?;
+        Ok(tokio::fs::File::from_std(file.into_file()))
+    }
+
+    /// Convert into an `AsyncRead` over the contents, from the tempfile or from memory
+    /// (requires the `tokio` feature)
+    #[cfg(feature = "tokio")]
+    pub fn into_async_read(self) -> Result<Box<dyn tokio::io::AsyncRead + Send + Unpin>, Error> {
+        match self.inner {
+            FileData::Disk(_) => Ok(Box::new(self.into_async_file()?)),
+            FileData::Memory(bytes) => Ok(Box::new(Cursor::new(bytes))),
+        }
+    }
+
+    /// Like `persist_in`, but run on tokio's blocking thread pool so the rename (or the write,
+    /// for a file held in memory) does not stall the executor (requires the `tokio` feature, and
+    /// so a tokio 1 runtime such as actix-web 4's)
+    #[cfg(feature = "tokio")]
+    pub async fn persist_in_async<P: AsRef<Path>>(self, dir: P) -> Result<PathBuf, Error> {
+        let new_path = dir.as_ref().join(&self.sanitized_file_name);
+        self.persist_at_async(&new_path).await.map(|_| new_path)
+    }
+
+    /// Like `persist_at`, but run on tokio's blocking thread pool so the rename (or the write,
+    /// for a file held in memory) does not stall the executor (requires the `tokio` feature, and
+    /// so a tokio 1 runtime such as actix-web 4's)
+    #[cfg(feature = "tokio")]
+    pub async fn persist_at_async<P: AsRef<Path>>(self, path: P) -> Result<tokio::fs::File, Error> {
+        let path = path.as_ref().to_path_buf();
+        tokio::task::spawn_blocking(move || self.persist_to(&path))
+            .await
+            .map_err(blocking_error)?
+            .map(tokio::fs::File::from_std)
+    }
+
+    /// Read the contents as a string, failing with `Error::Io` (of kind `InvalidData`) if they
+    /// are not valid UTF-8
+    pub fn to_utf8_string(&self) -> Result<String, Error> {
+        let mut s = String::new();
+        self.reader().and_then(|mut x| x.read_to_string(&mut s)).map_err(Error::Io)?;
+        Ok(s)
+    }
+
+    /// Read the contents as a string, replacing invalid UTF-8 sequences with `U+FFFD`
+    pub fn to_string_lossy(&self) -> Result<String, Error> {
+        let mut data = Vec::new();
+        self.reader().and_then(|mut x| x.read_to_end(&mut data)).map_err(Error::Io)?;
+        Ok(match String::from_utf8(data) {
+            Ok(s) => s,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        })
+    }
+
+    /// The path of the tempfile, e.g. to pass to an external tool, or `None` if the file is held
+    /// in memory
+    pub fn path(&self) -> Option<&Path> {
+        match self.inner {
+            FileData::Disk(ref file) => Some(file.path()),
+            FileData::Memory(_) => None,
+        }
+    }
+
+    /// The contents of a file held in memory (see `PartsConfig::with_in_memory_only` and
+    /// `PartsConfig::with_memory_threshold`), or `None` for a tempfile
+    pub fn bytes(&self) -> Option<&Bytes> {
+        match self.inner {
+            FileData::Memory(ref bytes) => Some(bytes),
+            FileData::Disk(_) => None,
+        }
+    }
+
+    /// The size in bytes, counted as the file was written while reading the request
+    ///
+    /// For a tempfile not collected by this crate (e.g., from `File::new`), this reads the file's
+    /// metadata instead (and is 0 if that fails).
+    pub fn size(&self) -> u64 {
+        match self.inner {
+            FileData::Memory(ref bytes) => bytes.len() as u64,
+            FileData::Disk(ref file) => {
+                self.size.or_else(|| file.as_file().metadata().ok().map(|x| x.len())).unwrap_or(0)
+            }
+        }
+    }
+
+    /// All headers sent with the file part, as lowercase names and values, in the order they
+    /// were sent (empty for a file not collected by this crate)
+    pub fn headers(&self) -> &[(String, Bytes)] {
+        &self.headers
+    }
+
+    /// The first header sent with the file part with the given name (matched without regard to
+    /// case), if it is valid UTF-8
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .and_then(|(_, v)| std::str::from_utf8(v).ok())
+    }
+
+    pub(crate) fn with_headers(mut self, headers: Vec<(String, Bytes)>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// The content type the file was sent with (`application/octet-stream` if unknown)
+    pub fn content_type(&self) -> &mime::Mime {
+        &self.content_type
+    }
+
+    /// The content type detected from the file's first bytes, regardless of what the client
+    /// declared, if it was recognized (requires the `infer` feature)
+    ///
+    /// The bytes are looked at as the file is collected, so this does not read the tempfile.
+    /// It is `None` for a tempfile not collected by this crate (e.g., from `File::new`).
+    #[cfg(feature = "infer")]
+    pub fn detected_content_type(&self) -> Option<&mime::Mime> {
+        self.detected_content_type.as_ref()
+    }
+
+    /// The hex-encoded SHA-256 of the file's contents (requires the `digest` feature)
+    ///
+    /// The hash is computed as the file is collected, so this does not read the tempfile.
+    /// It is `None` for a tempfile not collected by this crate (e.g., from `File::new`).
+    #[cfg(feature = "digest")]
+    pub fn sha256_hex(&self) -> Option<String> {
+        self.sha256.map(hex::encode)
+    }
+
+    /// The filename provided in the multipart/form-data request
+    pub fn original_file_name(&self) -> Option<&str> {
+        self.original_file_name.as_deref()
+    }
+
+    /// The sanitized version of the original file name, or generated name if none provided
+    pub fn sanitized_file_name(&self) -> &str {
+        &self.sanitized_file_name
+    }
+
+    #[deprecated(since = "0.5.4", note = "Please use the 'persist_in' function instead")]
+    /// Persist the tempfile to an existing directory. Uses the sanitized file name and returns
+    /// the full path
+    ///
+    /// NOTE: Because of how temporary file is stored, it cannot be persisted across filesystems.
+    /// Also neither the file contents nor the containing directory are
+    /// synchronized, so the update may not yet have reached the disk when
+    /// `persist` returns.
+    pub fn persist<P: AsRef<Path>>(self, dir: P) -> Result<PathBuf, Error> {
+        let new_path = dir.as_ref().join(&self.sanitized_file_name);
+        self.persist_to(&new_path).map(|_| new_path)
+    }
+
+    /// Persist the tempfile to an existing directory. Uses the sanitized file name and returns
+    /// the full path
+    ///
+    /// NOTE: Because of how temporary file is stored, it cannot be persisted across filesystems.
+    /// Also neither the file contents nor the containing directory are
+    /// synchronized, so the update may not yet have reached the disk when
+    /// `persist_in` returns.
+    ///
+    /// On Windows, absolute paths (including UNC shares) are passed to the OS in their `\\?\`
+    /// form, so long sanitized file names are not subject to `MAX_PATH`.
+    pub fn persist_in<P: AsRef<Path>>(self, dir: P) -> Result<PathBuf, Error> {
+        let new_path = dir.as_ref().join(&self.sanitized_file_name);
+        self.persist_to(&new_path).map(|_| new_path)
+    }
+
+    /// Like `persist_in`, but with a policy for a sanitized name that is already taken in the
+    /// directory (see `FileParts::persist_all_in_with`)
+    pub fn persist_in_with<P: AsRef<Path>>(
+        self,
+        dir: P,
+        policy: CollisionPolicy,
+    ) -> Result<PathBuf, Error> {
+        let dir = dir.as_ref();
+        match policy {
+            CollisionPolicy::Overwrite => self.persist_in(dir),
+            CollisionPolicy::ErrorIfExists => {
+                self.persist_new(dir, std::iter::empty()).map_err(Error::Io)
+            }
+            CollisionPolicy::AppendCounter => {
+                let counter = (1..).map(|n: usize| n.to_string());
+                self.persist_new(dir, counter).map_err(Error::Io)
+            }
+            CollisionPolicy::AppendUuid => {
+                let uuids = std::iter::repeat_with(|| uuid::Uuid::new_v4().to_simple().to_string());
+                self.persist_new(dir, uuids).map_err(Error::Io)
+            }
+        }
+    }
+
+    /// Persist the tempfile at the specified file path.
     ///
     /// NOTE: Because of how temporary file is stored, it cannot be persisted across filesystems.
     /// Also neither the file contents nor the containing directory are
     /// synchronized, so the update may not yet have reached the disk when
     /// `persist_at` returns.
     pub fn persist_at<P: AsRef<Path>>(self, path: P) -> Result<std::fs::File, Error> {
-        self.inner.persist(path).map_err(Error::TempFilePersistError)
+        self.persist_to(path.as_ref())
+    }
+
+    fn persist_to(self, path: &Path) -> Result<std::fs::File, Error> {
+        match self.inner {
+            FileData::Disk(file) => {
+                file.persist(os_path(path)).map_err(Error::TempFilePersistError)
+            }
+            FileData::Memory(bytes) => {
+                let mut file = std::fs::File::create(os_path(path)).map_err(Error::Io)?;
+                file.write_all(&bytes).map_err(Error::Io)?;
+                Ok(file)
+            }
+        }
+    }
+
+    /// Persist to a directory under the sanitized file name without replacing anything there,
+    /// trying the name with each of `suffixes` in turn while it is taken, and returning the path
+    ///
+    /// Fails with `AlreadyExists` once the suffixes run out.
+    fn persist_new<I>(self, dir: &Path, mut suffixes: I) -> std::io::Result<PathBuf>
+    where
+        I: Iterator<Item = String>,
+    {
+        let mut path = dir.join(&self.sanitized_file_name);
+        let mut inner = self.inner;
+        loop {
+            let error = match inner {
+                FileData::Disk(file) => match file.persist_noclobber(os_path(&path)) {
+                    Ok(_) => return Ok(path),
+                    Err(e) => {
+                        inner = FileData::Disk(e.file);
+                        e.error
+                    }
+                },
+                FileData::Memory(ref bytes) => {
+                    let written = std::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(os_path(&path))
+                        .and_then(|mut file| file.write_all(bytes));
+                    match written {
+                        Ok(()) => return Ok(path),
+                        Err(e) => e,
+                    }
+                }
+            };
+            match suffixes.next() {
+                Some(suffix) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                    path = dir.join(name_with_suffix(&self.sanitized_file_name, &suffix));
+                }
+                _ => return Err(error),
+            }
+        }
+    }
+
+    /// Read the contents, from the tempfile or from memory
+    pub(crate) fn reader(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        match self.inner {
+            FileData::Disk(ref file) => Ok(Box::new(std::fs::File::open(file.path())?)),
+            FileData::Memory(ref bytes) => Ok(Box::new(bytes.as_ref())),
+        }
     }
 
     pub fn new(
         file: NamedTempFile,
         original_file_name: Option<String>,
         mime_type: Option<&mime::Mime>,
+    ) -> Self {
+        Self::with_data(FileData::Disk(file), original_file_name, mime_type)
+    }
+
+    /// A file held in memory, as collected with `PartsConfig::with_in_memory_only`
+    pub fn new_in_memory(
+        bytes: Bytes,
+        original_file_name: Option<String>,
+        mime_type: Option<&mime::Mime>,
+    ) -> Self {
+        Self::with_data(FileData::Memory(bytes), original_file_name, mime_type)
+    }
+
+    fn with_data(
+        inner: FileData,
+        original_file_name: Option<String>,
+        mime_type: Option<&mime::Mime>,
     ) -> Self {
         let sanitized_file_name = match original_file_name {
             Some(ref s) => sanitize_filename::sanitize(s),
             None => {
                 let uuid = uuid::Uuid::new_v4().to_simple();
 
-                match mime_type
-                    .and_then(|mt| mime_guess::get_mime_extensions(mt))
-                    .and_then(|x| x.first())
-                {
+                match mime_type.and_then(mime_guess::get_mime_extensions).and_then(|x| x.first()) {
                     Some(ext) => format!("{}.{}", uuid, ext),
                     None => uuid.to_string(),
                 }
             }
         };
 
-        File { inner: file, sanitized_file_name, original_file_name }
+        let content_type = mime_type.cloned().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        let (detected_content_type, sha256) = match inner {
+            FileData::Memory(ref bytes) => {
+                let mut tracker = FileTracker::default();
+                tracker.push(bytes);
+                (tracker.content_type(), tracker.sha256())
+            }
+            FileData::Disk(_) => (None, None),
+        };
+        File {
+            inner,
+            sanitized_file_name,
+            original_file_name,
+            size: None,
+            content_type,
+            headers: Vec::new(),
+            detected_content_type,
+            sha256,
+            memory_pos: 0,
+        }
+    }
+
+    /// A tempfile collected from a request, with the number of bytes written to it and what
+    /// was tracked while writing them, rewound for reading
+    pub(crate) fn new_collected(
+        mut file: NamedTempFile,
+        original_file_name: Option<String>,
+        mime_type: &mime::Mime,
+        size: u64,
+        tracker: &FileTracker,
+    ) -> Self {
+        let _ = file.seek(SeekFrom::Start(0));
+        File {
+            size: Some(size),
+            detected_content_type: tracker.content_type(),
+            sha256: tracker.sha256(),
+            ..Self::new(file, original_file_name, Some(mime_type))
+        }
     }
 
     pub fn new_with_file_name(file: NamedTempFile, original_file_name: String) -> Self {
@@ -335,11 +1566,28 @@ impl File {
         dir: P,
         mode: u32,
     ) -> Result<PathBuf, Error> {
-        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
         let permissions = std::fs::Permissions::from_mode(mode);
-        std::fs::set_permissions(self.inner.path(), permissions).map_err(Error::Io)?;
         let new_path = dir.as_ref().join(&self.sanitized_file_name);
-        self.persist_in(&new_path)
+        match self.inner {
+            FileData::Disk(ref file) => {
+                std::fs::set_permissions(file.path(), permissions).map_err(Error::Io)?;
+                self.persist_to(&new_path)?;
+            }
+            FileData::Memory(ref bytes) => {
+                // Never more open than `mode` while the contents are written
+                let mut file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .mode(mode)
+                    .open(os_path(&new_path))
+                    .map_err(Error::Io)?;
+                file.set_permissions(permissions).map_err(Error::Io)?;
+                file.write_all(bytes).map_err(Error::Io)?;
+            }
+        }
+        Ok(new_path)
     }
 
     /// Persist the tempfile with 644 permissions on Unix
@@ -356,118 +1604,3635 @@ impl File {
 /// `FromRequest` configurator
 /// 
 /// When setting `App::app_data`, ensure that `PartsConfig` is wrapped in `Data`
-#[cfg(not(feature = "v4"))]
-#[derive(Default, Debug, Clone)]
+///
+/// The default configuration limits file fields to 10 MB, text fields to 64 KB (larger ones are
+/// saved as files) and requests to 20 files. The extractors use it when none is set, and
+/// deserialized configurations start from it; start from `PartsConfig::unlimited` to opt out.
+#[derive(Debug, Clone)]
 pub struct PartsConfig {
     text_limit: Option<usize>,
+    text_overflow_policy: Option<TextOverflowPolicy>,
+    total_text_limit: Option<usize>,
+    trim_text: bool,
+    strip_bom: bool,
+    normalize_newlines: bool,
+    strict_utf8: bool,
+    #[cfg(feature = "encoding_rs")]
+    decode_text_charsets: bool,
+    #[cfg(feature = "base64")]
+    decode_base64_texts: bool,
     file_limit: Option<usize>,
-    file_fields: Option<Vec<String>>,
-    text_fields: Option<Vec<String>>,
-    temp_dir: Option<PathBuf>,
+    total_limit: Option<usize>,
+    oversize_policy: Option<OversizePolicy>,
+    max_files: Option<usize>,
+    max_fields: Option<usize>,
+    max_parts: Option<usize>,
+    max_field_name_len: Option<usize>,
+    max_file_name_len: Option<usize>,
+    max_part_header_size: Option<usize>,
+    long_name_policy: Option<LongNamePolicy>,
+    file_fields: Option<Arc<[String]>>,
+    text_fields: Option<Arc<[String]>>,
+    case_insensitive_fields: bool,
+    normalize_array_fields: bool,
+    field_filter: Option<FieldFilter>,
+    only_fields: Option<Arc<[String]>>,
+    required_fields: Option<Arc<[String]>>,
+    sensitive_fields: Option<Arc<[String]>>,
+    duplicate_policy: Option<DuplicatePolicy>,
+    error_mode: Option<ErrorMode>,
+    error_handler: Option<ErrorHandler>,
+    #[cfg(feature = "regex")]
+    file_field_pattern: Option<regex::Regex>,
+    #[cfg(feature = "regex")]
+    text_field_pattern: Option<regex::Regex>,
+    content_type_files: Option<ContentTypeFiles>,
+    discard_files: bool,
+    skip_empty_files: bool,
+    strict_content_types: bool,
+    in_memory_limit: Option<usize>,
+    memory_threshold: Option<usize>,
+    allowed_mime_types: Option<Arc<[mime::Mime]>>,
+    denied_mime_types: Option<Arc<[mime::Mime]>>,
+    allowed_extensions: Option<Arc<[String]>>,
+    disallowed_file_policy: Option<DisallowedFilePolicy>,
+    file_mix: Option<FileMix>,
+    temp_dir: Option<Arc<Path>>,
+    temp_quota: Option<usize>,
+    create_temp_dir: bool,
+    #[cfg(unix)]
+    temp_dir_mode: Option<u32>,
+    temp_file_prefix: Option<Arc<str>>,
+    temp_file_suffix: Option<Arc<str>>,
+    #[cfg(unix)]
+    temp_file_mode: Option<u32>,
+    temp_dir_tiers: Option<Arc<[(usize, PathBuf)]>>,
+    #[cfg(feature = "fs2")]
+    min_free_space: Option<usize>,
+    field_temp_dirs: Option<Arc<[(String, PathBuf)]>>,
+    #[cfg(not(feature = "v1"))]
+    field_routes: Option<Arc<[(String, FieldHandler)]>>,
+    #[cfg(not(feature = "v1"))]
+    deadline: Option<Duration>,
+    #[cfg(not(feature = "v1"))]
+    field_timeout: Option<Duration>,
+    #[cfg(not(feature = "v1"))]
+    min_transfer_rate: Option<(usize, Duration)>,
+    #[cfg(not(feature = "v1"))]
+    max_transfer_rate: Option<usize>,
+    #[cfg(feature = "signature")]
+    signature: Option<signature::Signature>,
+}
+
+/// The same as `PartsConfig::recommended`
+impl Default for PartsConfig {
+    fn default() -> Self {
+        PartsConfig::recommended()
+    }
 }
 
-#[cfg(not(feature = "v4"))]
 impl PartsConfig {
+    /// The default configuration: file fields are limited to 10 MB, text fields to 64 KB and
+    /// requests to 20 files
+    pub fn recommended() -> Self {
+        PartsConfig {
+            text_limit: Some(64 * 1024),
+            file_limit: Some(10 * 1024 * 1024),
+            max_files: Some(20),
+            ..PartsConfig::unlimited()
+        }
+    }
+
+    /// A configuration without any limits
+    pub fn unlimited() -> Self {
+        PartsConfig {
+            text_limit: None,
+            text_overflow_policy: None,
+            total_text_limit: None,
+            trim_text: false,
+            strip_bom: false,
+            normalize_newlines: false,
+            strict_utf8: false,
+            #[cfg(feature = "encoding_rs")]
+            decode_text_charsets: false,
+            #[cfg(feature = "base64")]
+            decode_base64_texts: false,
+            file_limit: None,
+            total_limit: None,
+            oversize_policy: None,
+            max_files: None,
+            max_fields: None,
+            max_parts: None,
+            max_field_name_len: None,
+            max_file_name_len: None,
+            max_part_header_size: None,
+            long_name_policy: None,
+            file_fields: None,
+            text_fields: None,
+            case_insensitive_fields: false,
+            normalize_array_fields: false,
+            field_filter: None,
+            only_fields: None,
+            required_fields: None,
+            sensitive_fields: None,
+            duplicate_policy: None,
+            error_mode: None,
+            error_handler: None,
+            #[cfg(feature = "regex")]
+            file_field_pattern: None,
+            #[cfg(feature = "regex")]
+            text_field_pattern: None,
+            content_type_files: None,
+            discard_files: false,
+            skip_empty_files: false,
+            strict_content_types: false,
+            in_memory_limit: None,
+            memory_threshold: None,
+            allowed_mime_types: None,
+            denied_mime_types: None,
+            allowed_extensions: None,
+            disallowed_file_policy: None,
+            file_mix: None,
+            temp_dir: None,
+            temp_quota: None,
+            create_temp_dir: false,
+            #[cfg(unix)]
+            temp_dir_mode: None,
+            temp_file_prefix: None,
+            temp_file_suffix: None,
+            #[cfg(unix)]
+            temp_file_mode: None,
+            temp_dir_tiers: None,
+            #[cfg(feature = "fs2")]
+            min_free_space: None,
+            field_temp_dirs: None,
+            #[cfg(not(feature = "v1"))]
+            field_routes: None,
+            #[cfg(not(feature = "v1"))]
+            deadline: None,
+            #[cfg(not(feature = "v1"))]
+            field_timeout: None,
+            #[cfg(not(feature = "v1"))]
+            min_transfer_rate: None,
+            #[cfg(not(feature = "v1"))]
+            max_transfer_rate: None,
+            #[cfg(feature = "signature")]
+            signature: None,
+        }
+    }
+
     /// Any text fields above this limit will be converted to file fields
     pub fn with_text_limit(mut self, text_limit: usize) -> Self {
         self.text_limit = Some(text_limit);
         self
     }
 
-    /// Any file fields above this limit will be ignored
-    pub fn with_file_limit(mut self, file_limit: usize) -> Self {
-        self.file_limit = Some(file_limit);
+    /// What to do with text fields over `text_limit` (defaults to `SpillToFile`)
+    pub fn with_text_overflow_policy(mut self, policy: TextOverflowPolicy) -> Self {
+        self.text_overflow_policy = Some(policy);
         self
     }
 
-    /// Any form names that should be interpreted as files
-    pub fn with_file_fields(mut self, file_fields: Vec<String>) -> Self {
-        self.file_fields = Some(file_fields);
+    /// Fail the request with 413 Payload Too Large if the text fields held in memory add up
+    /// to more than this many bytes (`text_limit` only applies to each field)
+    pub fn with_total_text_limit(mut self, total_text_limit: usize) -> Self {
+        self.total_text_limit = Some(total_text_limit);
         self
     }
 
-    /// Any form names that should be interpreted as inline texts
-    pub fn with_text_fields(mut self, text_fields: Vec<String>) -> Self {
-        self.text_fields = Some(text_fields);
+    /// Strip leading and trailing whitespace (including the trailing CRLF some clients
+    /// append) from text fields as they are collected
+    pub fn with_trim_text(mut self, trim_text: bool) -> Self {
+        self.trim_text = trim_text;
         self
     }
 
-    /// To use a different location than the tempfile default
-    pub fn with_temp_dir<I: Into<PathBuf>>(mut self, temp_dir: I) -> Self {
-        self.temp_dir = Some(temp_dir.into());
+    /// Strip a leading UTF-8 byte order mark, as some Windows clients prepend, from text
+    /// fields as they are collected
+    pub fn with_strip_bom(mut self, strip_bom: bool) -> Self {
+        self.strip_bom = strip_bom;
         self
     }
-}
 
-#[cfg(feature = "v4")]
-pub use v4::PartsConfig;
+    /// Convert CRLF line endings in text fields (e.g., from a `textarea`) to LF as they are
+    /// collected
+    pub fn with_normalize_newlines(mut self, normalize_newlines: bool) -> Self {
+        self.normalize_newlines = normalize_newlines;
+        self
+    }
 
-#[derive(Debug)]
-enum Part {
-    Text(Bytes),
-    File(Result<File, Error>),
-}
+    /// Fail with 400 Bad Request, naming the field, when a text field is not valid UTF-8
+    /// (after any charset decoding)
+    ///
+    /// Otherwise such fields are kept, but left out of `TextParts::as_pairs` and
+    /// `TextParts::to_query_string`.
+    pub fn with_strict_utf8(mut self, strict_utf8: bool) -> Self {
+        self.strict_utf8 = strict_utf8;
+        self
+    }
 
-#[derive(Debug)]
-enum Buffer {
-    Cursor(Cursor<Vec<u8>>),
-    File(NamedTempFile),
-}
+    /// Transcode text fields that declare a charset other than UTF-8 (e.g.,
+    /// `text/plain; charset=windows-1252`) to UTF-8 as they are collected
+    ///
+    /// Bytes that are invalid in the declared charset are replaced with U+FFFD.
+    #[cfg(feature = "encoding_rs")]
+    pub fn with_text_charset_decoding(mut self, decode_text_charsets: bool) -> Self {
+        self.decode_text_charsets = decode_text_charsets;
+        self
+    }
 
-struct FileTooLarge {
-    limit: usize,
-}
+    /// Decode text fields sent with `Content-Transfer-Encoding: base64` as they are collected,
+    /// as some SOAP and email gateway clients do
+    ///
+    /// Fields that are not valid base64 fail with `Error::InvalidValue`. Text limits apply to
+    /// the encoded size.
+    #[cfg(feature = "base64")]
+    pub fn with_base64_text_decoding(mut self, decode_base64_texts: bool) -> Self {
+        self.decode_base64_texts = decode_base64_texts;
+        self
+    }
 
-#[cfg(test)]
-mod test {
-    use tempfile::NamedTempFile;
+    /// Any file fields above this limit will be ignored (see `with_oversize_policy`)
+    pub fn with_file_limit(mut self, file_limit: usize) -> Self {
+        self.file_limit = Some(file_limit);
+        self
+    }
 
-    use crate::File;
-    use std::{io::Write, iter};
+    /// `with_text_limit` with a size such as `"64KiB"` (see `parse_size`)
+    pub fn with_text_limit_str(self, text_limit: &str) -> Result<Self, Error> {
+        Ok(self.with_text_limit(parse_size(text_limit)?))
+    }
 
-    #[test]
-    pub fn create_file_with_size() {
-        // ARRANGE
-        let file_name = "my name jeff.txt".to_string();
-        let sanitized_file_name = sanitize_filename::sanitize(file_name.clone());
+    /// `with_file_limit` with a size such as `"10MB"` (see `parse_size`)
+    pub fn with_file_limit_str(self, file_limit: &str) -> Result<Self, Error> {
+        Ok(self.with_file_limit(parse_size(file_limit)?))
+    }
 
-        let size = 20usize;
-        let expected_content = iter::repeat(1u8).take(size).collect::<Vec<_>>();
+    /// `with_total_limit` with a size such as `"1GiB"` (see `parse_size`)
+    pub fn with_total_limit_str(self, total_limit: &str) -> Result<Self, Error> {
+        Ok(self.with_total_limit(parse_size(total_limit)?))
+    }
 
-        let mut name_tempfile = NamedTempFile::new().expect("Failed creating temp file.");
-        name_tempfile.write_all(&expected_content).expect("Failed writing to file.");
+    /// Any requests whose fields add up to more than this limit will be rejected
+    ///
+    /// Requests whose `Content-Length` is already over the limit are rejected before any of
+    /// the body is read. That check applies the limit to the whole body, which also counts the
+    /// multipart boundaries and part headers (typically 100-200 bytes per part), so leave that
+    /// much headroom above the field data you mean to accept.
+    pub fn with_total_limit(mut self, total_limit: usize) -> Self {
+        self.total_limit = Some(total_limit);
+        self
+    }
 
-        // ACT
-        let file = File::new_with_file_name(name_tempfile, file_name.clone());
-        let tempfile_path = file.inner.path().to_path_buf();
-        let content = std::fs::read(tempfile_path).expect("Can not read temporary file");
+    /// What to do with file fields over `file_limit` (defaults to `SkipPart`)
+    pub fn with_oversize_policy(mut self, oversize_policy: OversizePolicy) -> Self {
+        self.oversize_policy = Some(oversize_policy);
+        self
+    }
 
-        // ASSERT
-        assert_eq!(expected_content, content);
-        assert_eq!(file.original_file_name, Some(file_name));
-        assert_eq!(file.sanitized_file_name, sanitized_file_name);
+    /// Any requests with more file fields than this will be rejected
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
     }
 
-    #[test]
-    pub fn create_file_then_persist() {
-        // ARRANGE
-        let file_name = "create_file_then_persist.txt".to_string();
-        let file_path = std::env::temp_dir();
+    /// Any requests with more fields (text and file) than this will be rejected
+    ///
+    /// Fields dropped by `with_only_fields` or the field filter are not counted (see
+    /// `with_max_parts` to cap those as well).
+    pub fn with_max_fields(mut self, max_fields: usize) -> Self {
+        self.max_fields = Some(max_fields);
+        self
+    }
 
-        let size = 20usize;
-        let expected_content = iter::repeat(1u8).take(size).collect::<Vec<_>>();
+    /// Stop reading the request after this many parts, failing with 413 Payload Too Large
+    ///
+    /// This is a hard ceiling on the parts processed, checked before a part's headers are
+    /// looked at, and counts parts that are dropped (e.g., by `with_only_fields`). Set it above
+    /// `max_fields` to cut off boundary floods separately from ordinary field limits.
+    pub fn with_max_parts(mut self, max_parts: usize) -> Self {
+        self.max_parts = Some(max_parts);
+        self
+    }
 
-        let mut tempfile = NamedTempFile::new().expect("Failed creating temp file.");
-        tempfile.write_all(&expected_content).expect("Failed writing to file.");
+    /// Any field names longer than this many bytes will be handled per `long_name_policy`
+    pub fn with_max_field_name_len(mut self, max_field_name_len: usize) -> Self {
+        self.max_field_name_len = Some(max_field_name_len);
+        self
+    }
 
-        // ACT
-        let file = File::new_with_file_name(tempfile, file_name.clone());
-        file.persist_in(&file_path).expect("Failed persisting file");
+    /// Any filenames longer than this many bytes will be handled per `long_name_policy`
+    pub fn with_max_file_name_len(mut self, max_file_name_len: usize) -> Self {
+        self.max_file_name_len = Some(max_file_name_len);
+        self
+    }
 
-        // ASSERT
-        let content = std::fs::read(format!("{}/{}", file_path.to_string_lossy(), file_name))
-            .expect("Can not read temporary file");
+    /// Fail the request with 400 Bad Request if any field's headers (`Content-Disposition`,
+    /// `Content-Type` and any others) add up to more than this many bytes
+    pub fn with_max_part_header_size(mut self, max_part_header_size: usize) -> Self {
+        self.max_part_header_size = Some(max_part_header_size);
+        self
+    }
 
-        assert_eq!(expected_content, content);
+    /// What to do with names over the configured lengths (defaults to `Reject`)
+    pub fn with_long_name_policy(mut self, long_name_policy: LongNamePolicy) -> Self {
+        self.long_name_policy = Some(long_name_policy);
+        self
+    }
+
+    /// Any form names that should be interpreted as files
+    ///
+    /// Names may contain `*` (any run of characters) and `?` (any single character), e.g.
+    /// `"attachments[*]"`
+    pub fn with_file_fields(mut self, file_fields: Vec<String>) -> Self {
+        self.file_fields = Some(file_fields.into());
+        self
+    }
+
+    /// Any form names that should be interpreted as inline texts
+    ///
+    /// Names may contain `*` (any run of characters) and `?` (any single character), e.g.
+    /// `"meta_*"`
+    pub fn with_text_fields(mut self, text_fields: Vec<String>) -> Self {
+        self.text_fields = Some(text_fields.into());
+        self
+    }
+
+    /// Match field names without regard to ASCII case
+    ///
+    /// Field names are lowercased as they are collected, so `File` and `file` are both found
+    /// with `first("file")` or `take("file")`, and configured field names (e.g., `file_fields`,
+    /// `required_fields` or `with_temp_dir_for`) match either.
+    pub fn with_case_insensitive_fields(mut self, case_insensitive_fields: bool) -> Self {
+        self.case_insensitive_fields = case_insensitive_fields;
+        self
+    }
+
+    /// Collect PHP/Rails-style array fields under their base name, so `photos[]` and
+    /// `photos[0]` are both collected as `photos`
+    ///
+    /// Only an empty or numeric trailing index is removed (`user[name]` is left as is). This
+    /// happens before `file_fields`/`text_fields` are matched, so list the base name there.
+    pub fn with_normalize_array_fields(mut self, normalize_array_fields: bool) -> Self {
+        self.normalize_array_fields = normalize_array_fields;
+        self
+    }
+
+    /// Decide per field, from its name, file name and content type, whether it is collected
+    /// as usual, dropped, or forced to be a text or file field
+    ///
+    /// The filter runs before any other classification, so `ForceText`/`ForceFile` override
+    /// `file_fields`/`text_fields`. Dropped fields are drained without being stored.
+    pub fn with_field_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&str, Option<&str>, &mime::Mime) -> FieldAction + Send + Sync + 'static,
+    {
+        self.field_filter = Some(FieldFilter(Arc::new(filter)));
+        self
+    }
+
+    /// Only collect fields with these names (see `with_file_fields` for patterns)
+    ///
+    /// Any other field is drained without allocating a buffer or tempfile for it.
+    pub fn with_only_fields(mut self, only_fields: Vec<String>) -> Self {
+        self.only_fields = Some(only_fields.into());
+        self
+    }
+
+    /// Any form names matching this pattern should be interpreted as files
+    ///
+    /// Checked alongside `with_file_fields`, e.g. `Regex::new(r"^doc_\d+_revision_\d+$")`
+    #[cfg(feature = "regex")]
+    pub fn with_file_field_pattern(mut self, pattern: regex::Regex) -> Self {
+        self.file_field_pattern = Some(pattern);
+        self
+    }
+
+    /// Any form names matching this pattern should be interpreted as inline texts
+    ///
+    /// Checked alongside `with_text_fields`
+    #[cfg(feature = "regex")]
+    pub fn with_text_field_pattern(mut self, pattern: regex::Regex) -> Self {
+        self.text_field_pattern = Some(pattern);
+        self
+    }
+
+    /// Fail with 400 Bad Request, listing the missing names, unless each of these fields is
+    /// present as a text or a successfully saved file
+    ///
+    /// NOTE: fields consumed by a field router only count if the router returns them
+    pub fn with_required_fields(mut self, required_fields: Vec<String>) -> Self {
+        self.required_fields = Some(required_fields.into());
+        self
+    }
+
+    /// Print `«redacted»` instead of the values of text fields with these names (see
+    /// `with_file_fields` for patterns) when `Parts` or `TextParts` are debug formatted, so
+    /// passwords and tokens do not end up in logs
+    ///
+    /// This covers what the extractors return. A `Part` from `read_field` still prints its
+    /// value, as does `TextParts` built by hand until `TextParts::mark_sensitive` is called.
+    pub fn with_sensitive_fields(mut self, sensitive_fields: Vec<String>) -> Self {
+        self.sensitive_fields = Some(sensitive_fields.into());
+        self
+    }
+
+    /// What to do with fields (text or file) sharing a name (defaults to `KeepAll`)
+    pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = Some(duplicate_policy);
+        self
+    }
+
+    /// Whether a failed part fails the request (defaults to `FailFast`)
+    ///
+    /// With `ErrorMode::Collect`, oversize and disallowed files are recorded in `FileParts`
+    /// whatever their policies, and texts over the limit, duplicate fields and fields with an
+    /// invalid `Content-Type` are recorded in `Parts::failed`. Request-wide limits still fail
+    /// the request.
+    pub fn with_error_mode(mut self, error_mode: ErrorMode) -> Self {
+        self.error_mode = Some(error_mode);
+        self
+    }
+
+    /// Turn extraction errors into responses with this function, rather than responding with
+    /// `Error::status_code`
+    ///
+    /// NOTE: errors returned by field routers are passed through as they are
+    pub fn with_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(Error, &actix_web::HttpRequest) -> actix_web::Error + Send + Sync + 'static,
+    {
+        self.error_handler = Some(ErrorHandler(Arc::new(handler)));
+        self
+    }
+
+    /// Treat any field sent with a `Content-Type` other than `text/plain` as a file, whether or
+    /// not it has a filename
+    ///
+    /// Fields without a `Content-Type` header are classified as usual.
+    pub fn with_content_type_classification(mut self) -> Self {
+        self.content_type_files = Some(ContentTypeFiles::NotTextPlain);
+        self
+    }
+
+    /// Treat any field sent with one of these content types as a file, whether or not it has a
+    /// filename
+    ///
+    /// Types with a `*` subtype (e.g., `image/*`) match every subtype.
+    pub fn with_file_content_types(mut self, content_types: Vec<mime::Mime>) -> Self {
+        self.content_type_files = Some(ContentTypeFiles::Listed(content_types.into()));
+        self
+    }
+
+    /// Only accept file fields declaring one of these content types
+    ///
+    /// Types with a `*` subtype (e.g., `image/*`) match every subtype. Other files are handled
+    /// per `disallowed_file_policy`.
+    pub fn with_allowed_mime_types(mut self, mime_types: Vec<mime::Mime>) -> Self {
+        self.allowed_mime_types = Some(mime_types.into());
+        self
+    }
+
+    /// Never accept file fields declaring one of these content types
+    ///
+    /// Types with a `*` subtype (e.g., `video/*`) match every subtype. These files are handled
+    /// per `disallowed_file_policy`.
+    pub fn with_denied_mime_types(mut self, mime_types: Vec<mime::Mime>) -> Self {
+        self.denied_mime_types = Some(mime_types.into());
+        self
+    }
+
+    /// Only accept file fields whose sanitized filename has one of these extensions
+    ///
+    /// Extensions are given without the dot and compared case-insensitively. Files sent without
+    /// a filename are checked against the extension generated from their content type. Other
+    /// files are handled per `disallowed_file_policy`.
+    pub fn with_allowed_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.allowed_extensions = Some(extensions.into());
+        self
+    }
+
+    /// What to do with file fields that are not allowed (defaults to `Record`)
+    pub fn with_disallowed_file_policy(mut self, policy: DisallowedFilePolicy) -> Self {
+        self.disallowed_file_policy = Some(policy);
+        self
+    }
+
+    /// Reject requests whose file fields do not fit any of these alternatives (see `FileMix`)
+    ///
+    /// This is checked as each file field arrives, before anything is written for it.
+    pub fn with_file_mix(mut self, file_mix: FileMix) -> Self {
+        self.file_mix = Some(file_mix);
+        self
+    }
+
+    /// Drain file fields without storing them anywhere, recording only their metadata in
+    /// `Parts::discarded_files`, e.g. for validation-only preflight endpoints
+    pub fn with_discard_files(mut self, discard_files: bool) -> Self {
+        self.discard_files = discard_files;
+        self
+    }
+
+    /// Drop file fields that arrive with no file name and no contents, as browsers send for
+    /// file inputs left empty, instead of collecting them as zero-byte files
+    pub fn with_skip_empty_files(mut self, skip_empty_files: bool) -> Self {
+        self.skip_empty_files = skip_empty_files;
+        self
+    }
+
+    /// Fail parts whose `Content-Type` header cannot be parsed with `Error::InvalidContentType`
+    /// (400 Bad Request, or a failed part under `ErrorMode::Collect`)
+    ///
+    /// By default such headers are ignored, and the part is treated as if it had none.
+    pub fn with_strict_content_types(mut self, strict_content_types: bool) -> Self {
+        self.strict_content_types = strict_content_types;
+        self
+    }
+
+    /// Keep file fields in memory instead of tempfiles, so the filesystem is never touched
+    ///
+    /// Requests whose fields add up to more than `max_total_bytes` are rejected, as are those
+    /// whose `Content-Length` is over it, as with `with_total_limit`. Files are read with
+    /// `File::bytes`, and the tmp directory settings are ignored.
+    pub fn with_in_memory_only(mut self, max_total_bytes: usize) -> Self {
+        self.in_memory_limit = Some(max_total_bytes);
+        self
+    }
+
+    /// Keep file fields in memory until they grow past this many bytes, only then moving them
+    /// to a tempfile
+    ///
+    /// Files that stay under the threshold are read with `File::bytes` (see `File`), which
+    /// saves the tempfile and blocking I/O for typical small uploads.
+    pub fn with_memory_threshold(mut self, memory_threshold: usize) -> Self {
+        self.memory_threshold = Some(memory_threshold);
+        self
+    }
+
+    /// To use a different location than the tempfile default
+    pub fn with_temp_dir<I: Into<PathBuf>>(mut self, temp_dir: I) -> Self {
+        self.temp_dir = Some(temp_dir.into().into());
+        self
+    }
+
+    /// Fail the request with 413 Payload Too Large if the tempfiles created for it add up to
+    /// more than this many bytes, however the files are sized individually
+    pub fn with_temp_quota(mut self, temp_quota: usize) -> Self {
+        self.temp_quota = Some(temp_quota);
+        self
+    }
+
+    /// Create the tmp directories (and their parents) if they are missing when a tempfile is
+    /// first put in them
+    pub fn with_create_temp_dir(mut self, create_temp_dir: bool) -> Self {
+        self.create_temp_dir = create_temp_dir;
+        self
+    }
+
+    /// Create missing tmp directories with these permissions on Unix (e.g., `0o700`), rather
+    /// than deriving them from the umask (see `with_create_temp_dir`)
+    #[cfg(unix)]
+    pub fn with_temp_dir_mode(mut self, mode: u32) -> Self {
+        self.temp_dir_mode = Some(mode);
+        self
+    }
+
+    /// Start the names of tempfiles with this, rather than `tempfile`'s default (`.tmp`)
+    pub fn with_temp_file_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.temp_file_prefix = Some(prefix.into().into());
+        self
+    }
+
+    /// End the names of tempfiles with this (by default, they have no suffix)
+    pub fn with_temp_file_suffix<S: Into<String>>(mut self, suffix: S) -> Self {
+        self.temp_file_suffix = Some(suffix.into().into());
+        self
+    }
+
+    /// Create tempfiles with these permissions on Unix (e.g., `0o600`), rather than deriving
+    /// them from the umask
+    #[cfg(unix)]
+    pub fn with_temp_file_mode(mut self, mode: u32) -> Self {
+        self.temp_file_mode = Some(mode);
+        self
+    }
+
+    /// Move file fields to a different tmp directory once they grow past a size threshold
+    ///
+    /// Each entry is a `(threshold, dir)` pair: a file larger than `threshold` bytes is kept in
+    /// `dir` (the largest matching threshold wins). Smaller files stay in `temp_dir` (or the
+    /// tempfile default), so small uploads can use e.g. a tmpfs while large ones go to disk.
+    pub fn with_tiered_temp_dirs<I: Into<PathBuf>>(mut self, tiers: Vec<(usize, I)>) -> Self {
+        let mut tiers = tiers.into_iter().map(|(x, dir)| (x, dir.into())).collect::<Vec<_>>();
+        tiers.sort_by_key(|(x, _)| *x);
+        self.temp_dir_tiers = Some(tiers.into());
+        self
+    }
+
+    /// Fail the request with 507 Insufficient Storage rather than leave less than this many
+    /// bytes free on the filesystem of a tempfile
+    ///
+    /// This is checked when each tempfile is created and before each write to it.
+    #[cfg(feature = "fs2")]
+    pub fn with_min_free_space(mut self, min_free_space: usize) -> Self {
+        self.min_free_space = Some(min_free_space);
+        self
+    }
+
+    /// Spool fields matching this name (see `with_file_fields` for patterns) to a different tmp
+    /// directory, e.g. to put large video uploads on a bigger volume
+    ///
+    /// This takes precedence over `temp_dir` and `with_tiered_temp_dirs`, and can be called
+    /// more than once to map several names.
+    pub fn with_temp_dir_for<I: Into<PathBuf>>(mut self, name: &str, temp_dir: I) -> Self {
+        let mut dirs =
+            self.field_temp_dirs.iter().flat_map(|x| x.iter()).cloned().collect::<Vec<_>>();
+        dirs.push((name.to_string(), temp_dir.into()));
+        self.field_temp_dirs = Some(dirs.into());
+        self
+    }
+
+    /// Hand fields matching this name (see `with_file_fields` for patterns) to an async handler
+    /// as soon as they have been read
+    ///
+    /// The handler receives a `Parts` holding just that field and returns whatever should
+    /// still be collected into the extracted `Parts` (e.g., an empty `Parts` once a file has
+    /// been moved to storage). This can be called more than once to route several names.
+    #[cfg(not(feature = "v1"))]
+    pub fn with_field_router<F, Fut>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(Parts) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Parts, actix_web::Error>> + 'static,
+    {
+        let handler =
+            FieldHandler(Arc::new(move |parts| -> PartsFuture { Box::pin(handler(parts)) }));
+        let mut routes =
+            self.field_routes.iter().flat_map(|x| x.iter()).cloned().collect::<Vec<_>>();
+        routes.push((name.to_string(), handler));
+        self.field_routes = Some(routes.into());
+        self
+    }
+
+    /// Fail the request with 408 Request Timeout if the whole multipart body has not been read
+    /// within this time, dropping any tempfiles created so far
+    #[cfg(not(feature = "v1"))]
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Fail the request with 408 Request Timeout, naming the field, if a field goes this long
+    /// without sending its next chunk, or if the body goes this long between fields
+    #[cfg(not(feature = "v1"))]
+    pub fn with_field_timeout(mut self, field_timeout: Duration) -> Self {
+        self.field_timeout = Some(field_timeout);
+        self
+    }
+
+    /// Fail the request with 408 Request Timeout if, once `grace_period` has passed, the body
+    /// has been arriving at fewer than `bytes_per_sec` bytes per second on average
+    ///
+    /// This also cuts off clients that stop sending altogether, as soon as the bytes they
+    /// have sent no longer cover the time taken.
+    #[cfg(not(feature = "v1"))]
+    pub fn with_min_transfer_rate(mut self, bytes_per_sec: usize, grace_period: Duration) -> Self {
+        self.min_transfer_rate = Some((bytes_per_sec, grace_period));
+        self
+    }
+
+    /// Read the body no faster than `bytes_per_sec` bytes per second on average, pausing
+    /// between chunks as needed, so a few fast uploads cannot saturate disks or the blocking
+    /// pool
+    #[cfg(not(feature = "v1"))]
+    pub fn with_max_transfer_rate(mut self, bytes_per_sec: usize) -> Self {
+        self.max_transfer_rate = Some(bytes_per_sec);
+        self
+    }
+
+    /// Reject requests that are not signed as configured (see the `signature` module)
+    #[cfg(feature = "signature")]
+    pub fn with_signature(mut self, signature: signature::Signature) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// Check for problems that would otherwise only show up as failing requests
+    ///
+    /// This checks that `file_limit` is not above `total_limit` and that tempfiles can be
+    /// created in the tmp directories. The extractors call it for every request and respond with
+    /// 500 Internal Server Error (naming the problem) if it fails; call it once at startup as
+    /// well, before handing the configuration to the `App`, to find out before the first upload.
+    ///
+    /// A tmp directory is only probed until a tempfile has been created in it once in the
+    /// process, so requests do not touch the filesystem here after the first.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: String| Err(Error::InvalidConfig { reason });
+
+        if let (Some(file_limit), Some(total_limit)) = (self.file_limit, self.total_limit) {
+            if file_limit > total_limit {
+                return invalid(format!(
+                    "file_limit ({} bytes) is above total_limit ({} bytes)",
+                    file_limit, total_limit
+                ));
+            }
+        }
+
+        if self.in_memory_limit.is_none() {
+            let tiers = self.temp_dir_tiers.iter().flat_map(|x| x.iter()).map(|(_, x)| x.as_path());
+            for dir in self.temp_dir.as_deref().into_iter().chain(tiers) {
+                if temp_dir_checked(dir) {
+                    continue;
+                }
+                if let Err(e) = temp_file_in(self, dir) {
+                    return invalid(format!("cannot create tempfiles in {}: {}", dir.display(), e));
+                }
+                remember_temp_dir(dir);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "v1"))]
+    fn field_route(&self, name: &str) -> Option<&FieldHandler> {
+        self.field_routes
+            .iter()
+            .flat_map(|x| x.iter())
+            .find(|(x, _)| glob_match(x, name))
+            .map(|(_, h)| h)
+    }
+
+    /// The most bytes of field data a request may send, from `total_limit` and the in-memory
+    /// budget
+    fn total_byte_limit(&self) -> Option<usize> {
+        match (self.total_limit, self.in_memory_limit) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        }
+    }
+
+    /// Check a request's `Content-Length` before reading its body
+    ///
+    /// This applies the field data limit to the whole body, boundaries and part headers
+    /// included, since their size is not known until the body is read.
+    fn check_content_length(&self, content_length: Option<usize>) -> Result<(), Error> {
+        match (self.total_byte_limit(), content_length) {
+            (Some(limit), Some(len)) if len > limit => Err(Error::RequestTooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the size of a field's headers, counted as `name: value\r\n` lines
+    fn check_part_header_size(&self, size: usize) -> Result<(), Error> {
+        match self.max_part_header_size {
+            Some(limit) if size > limit => Err(Error::HeadersTooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a text field that has grown past `text_limit`
+    fn check_text_overflow(&self, name: &str) -> Result<(), Error> {
+        match (self.text_overflow_policy, self.text_limit) {
+            (Some(TextOverflowPolicy::Error), Some(limit)) => {
+                Err(Error::TextTooLarge { limit, name: name.to_string() })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether any of the given field name patterns matches `name`
+    fn lists_field(&self, fields: Option<&Arc<[String]>>, name: &str) -> bool {
+        fields.iter().flat_map(|x| x.iter()).any(|x| self.matches_field(x, name))
+    }
+
+    /// Whether a field name pattern matches `name`, without regard to case if configured
+    fn matches_field(&self, pattern: &str, name: &str) -> bool {
+        if self.case_insensitive_fields {
+            glob_match(&pattern.to_ascii_lowercase(), &name.to_ascii_lowercase())
+        } else {
+            glob_match(pattern, name)
+        }
+    }
+
+    /// What to do with a field, from `only_fields` and the field filter (`Accept` otherwise)
+    fn field_action(&self, name: &str, file_name: Option<&str>, mime: &mime::Mime) -> FieldAction {
+        if self.only_fields.is_some() && !self.lists_field(self.only_fields.as_ref(), name) {
+            return FieldAction::Discard;
+        }
+        self.field_filter.as_ref().map_or(FieldAction::Accept, |x| (x.0)(name, file_name, mime))
+    }
+
+    /// The name a field is collected under
+    fn field_name(&self, mut name: String) -> String {
+        if self.normalize_array_fields {
+            let base_len = name
+                .strip_suffix(']')
+                .and_then(|x| x.rsplit_once('['))
+                .filter(|(_, index)| index.bytes().all(|x| x.is_ascii_digit()))
+                .map(|(base, _)| base.len());
+            if let Some(base_len) = base_len {
+                name.truncate(base_len);
+            }
+        }
+        if self.case_insensitive_fields {
+            name.make_ascii_lowercase();
+        }
+        name
+    }
+
+    /// The collected value of a text field, decoded and trimmed if configured
+    fn text_bytes(&self, mut data: Vec<u8>, mime: &mime::Mime) -> Bytes {
+        #[cfg(feature = "encoding_rs")]
+        {
+            let encoding = mime
+                .get_param(mime::CHARSET)
+                .filter(|_| self.decode_text_charsets)
+                .and_then(|x| encoding_rs::Encoding::for_label(x.as_str().as_bytes()))
+                .filter(|x| *x != encoding_rs::UTF_8);
+            if let Some(encoding) = encoding {
+                data = encoding.decode(&data).0.into_owned().into_bytes();
+            }
+        }
+        #[cfg(not(feature = "encoding_rs"))]
+        let _ = mime;
+
+        if self.strip_bom && data.starts_with(b"\xef\xbb\xbf") {
+            data.drain(..3);
+        }
+        if self.normalize_newlines {
+            data = data
+                .iter()
+                .enumerate()
+                .filter(|(i, x)| !(**x == b'\r' && data.get(i + 1) == Some(&b'\n')))
+                .map(|(_, x)| *x)
+                .collect();
+        }
+        if self.trim_text {
+            let end = data.iter().rposition(|x| !x.is_ascii_whitespace()).map_or(0, |x| x + 1);
+            data.truncate(end);
+            let start = data.iter().position(|x| !x.is_ascii_whitespace()).unwrap_or(end);
+            data.drain(..start);
+        }
+        Bytes::from(data)
+    }
+
+    /// The part for a text field, checked for valid UTF-8 with `strict_utf8`
+    ///
+    /// `base64_encoded` is whether the field declared a base64 transfer encoding, and
+    /// `declared_type` the `Content-Type` it was sent with, if any.
+    fn text_part(
+        &self,
+        name: &str,
+        data: Vec<u8>,
+        mime: &mime::Mime,
+        base64_encoded: bool,
+        declared_type: Option<mime::Mime>,
+    ) -> Result<Part, Error> {
+        #[cfg(feature = "base64")]
+        let data = if self.decode_base64_texts && base64_encoded {
+            let data = data.into_iter().filter(|x| !x.is_ascii_whitespace()).collect::<Vec<_>>();
+            match base64::decode(&data) {
+                Ok(x) => x,
+                Err(e) => {
+                    return self.fail_part(Error::InvalidValue {
+                        field: name.into(),
+                        reason: format!("Invalid base64: {}", e),
+                    });
+                }
+            }
+        } else {
+            data
+        };
+        #[cfg(not(feature = "base64"))]
+        let _ = base64_encoded;
+
+        let text = self.text_bytes(data, mime);
+        if self.strict_utf8 && std::str::from_utf8(&text).is_err() {
+            return self.fail_part(Error::InvalidUtf8 { field: name.into() });
+        }
+        Ok(Part::Text(text, declared_type))
+    }
+
+    fn rejects_oversize_files(&self) -> bool {
+        self.oversize_policy == Some(OversizePolicy::RejectRequest) && !self.collects_errors()
+    }
+
+    fn rejects_disallowed_files(&self) -> bool {
+        self.disallowed_file_policy == Some(DisallowedFilePolicy::Reject) && !self.collects_errors()
+    }
+
+    fn collects_errors(&self) -> bool {
+        self.error_mode == Some(ErrorMode::Collect)
+    }
+
+    /// The response for an extraction error, from the error handler if there is one
+    fn error_response(&self, e: Error, req: &actix_web::HttpRequest) -> actix_web::Error {
+        match self.error_handler {
+            Some(ref handler) => (handler.0)(e, req),
+            None => e.into(),
+        }
+    }
+
+    /// Record a failed part under `ErrorMode::Collect`, or fail the request
+    fn fail_part(&self, e: Error) -> Result<Part, Error> {
+        if self.collects_errors() {
+            Ok(Part::Failed(e))
+        } else {
+            Err(e)
+        }
+    }
+
+    /// Whether a file of `len` bytes should be held in memory rather than a tempfile
+    fn in_memory_for(&self, len: usize) -> bool {
+        self.in_memory_limit.is_some() || self.memory_threshold.is_some_and(|x| len <= x)
+    }
+
+    /// Whether a file field of `len` bytes should be dropped as an empty file input
+    fn skips_empty_file(&self, len: usize, file_name: Option<&str>) -> bool {
+        self.skip_empty_files && len == 0 && file_name.is_none_or(str::is_empty)
+    }
+
+    /// The configuration for a field mapped with `with_temp_dir_for`, which uses the mapped
+    /// directory in place of `temp_dir` and the tiers
+    fn for_field(&self, name: &str) -> Option<Self> {
+        let (_, dir) = self
+            .field_temp_dirs
+            .iter()
+            .flat_map(|x| x.iter())
+            .find(|(x, _)| self.matches_field(x, name))?;
+        let mut cfg = self.clone().with_temp_dir(dir.as_path());
+        cfg.temp_dir_tiers = None;
+        Some(cfg)
+    }
+
+    fn temp_dir_for(&self, len: usize) -> Option<&Path> {
+        self.temp_dir_tiers
+            .iter()
+            .flat_map(|x| x.iter())
+            .rfind(|(threshold, _)| len > *threshold)
+            .map(|(_, dir)| dir.as_path())
+            .or(self.temp_dir.as_deref())
+    }
+}
+
+/// Which declared content types mark a field as a file
+#[derive(Debug, Clone)]
+enum ContentTypeFiles {
+    NotTextPlain,
+    Listed(Arc<[mime::Mime]>),
+}
+
+impl ContentTypeFiles {
+    fn is_match(&self, content_type: &mime::Mime) -> bool {
+        match self {
+            ContentTypeFiles::NotTextPlain => content_type.essence_str() != mime::TEXT_PLAIN,
+            ContentTypeFiles::Listed(types) => types.iter().any(|x| mime_match(x, content_type)),
+        }
+    }
+}
+
+/// Match a content type against a pattern whose type or subtype may be `*`
+fn mime_match(pattern: &mime::Mime, content_type: &mime::Mime) -> bool {
+    (pattern.type_() == mime::STAR || pattern.type_() == content_type.type_())
+        && (pattern.subtype() == mime::STAR || pattern.subtype() == content_type.subtype())
+}
+
+/// Rules for the mix of files a request may contain, e.g. "at most 10 images OR 1 video"
+///
+/// ```rust
+/// let mix = awmp::FileMix::new()
+///     .or(vec![(mime::IMAGE_STAR, 10)])
+///     .or(vec![("video/*".parse().unwrap(), 1)]);
+/// ```
+///
+/// A request is accepted while its files fit at least one alternative. Each alternative caps
+/// the number of files per content type (`*` matches any type or subtype); a file is counted
+/// against the first cap it matches, and files matching no cap rule the alternative out.
+#[derive(Debug, Clone, Default)]
+pub struct FileMix {
+    alternatives: Vec<Vec<(mime::Mime, usize)>>,
+}
+
+impl FileMix {
+    /// No alternatives yet; on its own, this rejects every file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an alternative, as `(content type, max files)` caps
+    pub fn or(mut self, caps: Vec<(mime::Mime, usize)>) -> Self {
+        self.alternatives.push(caps);
+        self
+    }
+
+    fn allows(&self, mime_types: &[mime::Mime]) -> bool {
+        self.alternatives.iter().any(|caps| {
+            let mut counts = vec![0; caps.len()];
+            mime_types.iter().all(|mime_type| {
+                match caps.iter().position(|(x, _)| mime_match(x, mime_type)) {
+                    Some(i) => {
+                        counts[i] += 1;
+                        counts[i] <= caps[i].1
+                    }
+                    None => false,
+                }
+            })
+        })
+    }
+}
+
+#[cfg(not(feature = "v1"))]
+type PartsFuture =
+    std::pin::Pin<Box<dyn std::future::Future<Output = Result<Parts, actix_web::Error>>>>;
+
+/// An async handler registered with `PartsConfig::with_field_router`
+#[cfg(not(feature = "v1"))]
+#[derive(Clone)]
+struct FieldHandler(Arc<dyn Fn(Parts) -> PartsFuture + Send + Sync>);
+
+#[cfg(not(feature = "v1"))]
+impl std::fmt::Debug for FieldHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FieldHandler")
+    }
+}
+
+/// Parse a human-readable size such as `"512"`, `"10MB"` or `"512KiB"` into a number of bytes
+///
+/// Units are case-insensitive: `B`, decimal `KB`/`MB`/`GB`/`TB` (powers of 1000) and binary
+/// `KiB`/`MiB`/`GiB`/`TiB` (powers of 1024). A number without a unit is in bytes.
+pub fn parse_size(s: &str) -> Result<usize, Error> {
+    let invalid = || Error::InvalidSize { value: s.to_string() };
+
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number = number.parse::<usize>().map_err(|_| invalid())?;
+    let multiplier: usize = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000 * 1000,
+        "gb" => 1000 * 1000 * 1000,
+        "tb" => 1000 * 1000 * 1000 * 1000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+
+    number.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+/// What to do with a field, as decided by `PartsConfig::with_field_filter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldAction {
+    /// Collect the field as usual
+    Accept,
+    /// Drain the field without storing it, leaving it out of `Parts`
+    Discard,
+    /// Collect the field as a text, even if it was sent as a file
+    ForceText,
+    /// Collect the field as a file, even if it was sent as a text
+    ForceFile,
+}
+
+type FieldFilterFn = dyn Fn(&str, Option<&str>, &mime::Mime) -> FieldAction + Send + Sync;
+
+/// A predicate registered with `PartsConfig::with_field_filter`
+#[derive(Clone)]
+struct FieldFilter(Arc<FieldFilterFn>);
+
+impl std::fmt::Debug for FieldFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FieldFilter")
+    }
+}
+
+type ErrorHandlerFn = dyn Fn(Error, &actix_web::HttpRequest) -> actix_web::Error + Send + Sync;
+
+/// A function registered with `PartsConfig::with_error_handler`
+#[derive(Clone)]
+struct ErrorHandler(Arc<ErrorHandlerFn>);
+
+impl std::fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ErrorHandler")
+    }
+}
+
+/// Field names without repeats, in the order first seen
+fn unique_keys<'a>(keys: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut out = Vec::new();
+    for key in keys {
+        if !out.contains(&key) {
+            out.push(key);
+        }
+    }
+    out
+}
+
+/// Stable sort of parts by name, keeping what is known about each part alongside
+fn sort_indexed_by_name<T, M>(parts: &mut Vec<(String, T)>, meta: &mut Vec<M>) {
+    let mut zipped = meta.drain(..).zip(parts.drain(..)).collect::<Vec<_>>();
+    zipped.sort_by(|(_, (a, _)), (_, (b, _))| a.cmp(b));
+    let (x, y) = zipped.into_iter().unzip();
+    *meta = x;
+    *parts = y;
+}
+
+/// Split a field name like `user[tags][0]` into `["user", "tags", "0"]`, or keep it whole if
+/// the brackets are malformed
+#[cfg(feature = "serde_json")]
+fn json_key_path(key: &str) -> Vec<&str> {
+    let (head, mut rest) = match key.find('[') {
+        Some(i) if i > 0 => key.split_at(i),
+        _ => return vec![key],
+    };
+
+    let mut path = vec![head];
+    while !rest.is_empty() {
+        match rest.strip_prefix('[').and_then(|x| x.find(']').map(|end| (x, end))) {
+            Some((x, end)) => {
+                path.push(&x[..end]);
+                rest = &x[end + 1..];
+            }
+            None => return vec![key],
+        }
+    }
+    path
+}
+
+/// Insert a value into a JSON tree at the given path (see `TextParts::to_json_value`)
+#[cfg(feature = "serde_json")]
+fn insert_json_value(node: &mut serde_json::Value, path: &[&str], val: &str) {
+    use serde_json::Value;
+
+    let (key, rest) = match path.split_first() {
+        Some(x) => x,
+        None => {
+            match node {
+                Value::Null => *node = Value::String(val.into()),
+                Value::String(_) => *node = Value::Array(vec![node.take(), val.into()]),
+                Value::Array(x) => x.push(val.into()),
+                _ => {}
+            }
+            return;
+        }
+    };
+
+    if key.is_empty() || key.bytes().all(|x| x.is_ascii_digit()) {
+        if node.is_null() {
+            *node = Value::Array(Vec::new());
+        }
+        if let Value::Array(x) = node {
+            let index = key.parse::<usize>().ok().filter(|i| *i < x.len()).unwrap_or(x.len());
+            if index == x.len() {
+                x.push(Value::Null);
+            }
+            insert_json_value(&mut x[index], rest, val);
+        }
+    } else {
+        if node.is_null() {
+            *node = Value::Object(Default::default());
+        }
+        if let Value::Object(x) = node {
+            insert_json_value(x.entry(*key).or_insert(Value::Null), rest, val);
+        }
+    }
+}
+
+/// Match a field name against a pattern where `*` matches any run of characters and `?` any
+/// single character
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    let (mut pi, mut ni) = (0, 0);
+    let mut backtrack = None;
+
+    while ni < name.len() {
+        match pattern.get(pi) {
+            Some('*') => {
+                backtrack = Some((pi, ni));
+                pi += 1;
+            }
+            Some(c) if *c == '?' || *c == name[ni] => {
+                pi += 1;
+                ni += 1;
+            }
+            _ => match backtrack {
+                Some((star_pi, star_ni)) => {
+                    backtrack = Some((star_pi, star_ni + 1));
+                    pi = star_pi + 1;
+                    ni = star_ni + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[pi..].iter().all(|c| *c == '*')
+}
+
+/// How to handle text fields over the configured `text_limit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum TextOverflowPolicy {
+    /// Fail the request with 400 Bad Request, naming the field
+    Error,
+    /// Save the field as a file instead, subject to the same allow lists, file limits and
+    /// `with_discard_files` as file fields
+    SpillToFile,
+}
+
+/// How to handle file fields over the configured `file_limit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum OversizePolicy {
+    /// Fail the request with 413 Payload Too Large
+    RejectRequest,
+    /// Discard the file, recording `Error::FileTooLarge` in `FileParts`
+    SkipPart,
+    /// Keep the file cut down to the limit, discarding the rest of its data
+    Truncate,
+}
+
+/// How much of a chunk that brings a file to `len` bytes should be written, or the exceeded
+/// limit if the file should be discarded
+fn file_chunk_len(cfg: &PartsConfig, chunk_len: usize, len: usize) -> Result<usize, usize> {
+    match cfg.file_limit {
+        Some(limit) if len > limit => match cfg.oversize_policy {
+            Some(OversizePolicy::Truncate) => Ok(chunk_len.saturating_sub(len - limit)),
+            _ => Err(limit),
+        },
+        _ => Ok(chunk_len),
+    }
+}
+
+/// How many bytes were written for a file of `len` bytes (see `file_chunk_len`)
+fn written_file_len(cfg: &PartsConfig, len: usize) -> u64 {
+    cfg.file_limit.map_or(len, |limit| len.min(limit)) as u64
+}
+
+/// How to handle field names and filenames over the configured lengths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum LongNamePolicy {
+    /// Cut the name down to the limit
+    Truncate,
+    /// Fail the request with 400 Bad Request
+    Reject,
+}
+
+/// How `FileParts::persist_all_in_with` handles a file whose name is already taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Replace the existing file
+    Overwrite,
+    /// Fail with an `Error::Io` of kind `AlreadyExists`, leaving the existing file in place
+    ErrorIfExists,
+    /// Add `-1`, `-2`, … to the file stem until the name is free
+    AppendCounter,
+    /// Add a random UUID to the file stem
+    AppendUuid,
+}
+
+/// Insert a suffix before a file name's extension, e.g. `photo.jpg` to `photo-1.jpg`
+fn name_with_suffix(name: &str, suffix: &str) -> String {
+    match name.rfind('.').filter(|i| *i > 0) {
+        Some(i) => format!("{}-{}{}", &name[..i], suffix, &name[i..]),
+        None => format!("{}-{}", name, suffix),
+    }
+}
+
+/// How to handle fields sharing a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum DuplicatePolicy {
+    /// Keep the first field, discarding later ones
+    KeepFirst,
+    /// Keep the last field, discarding earlier ones
+    KeepLast,
+    /// Keep every field, in arrival order
+    KeepAll,
+    /// Fail the request with 400 Bad Request
+    Reject,
+}
+
+/// Whether a failed part fails the request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum ErrorMode {
+    /// Fail the request with the first error
+    FailFast,
+    /// Keep reading, recording the error in `Parts`
+    Collect,
+}
+
+/// Add a field to the collected parts, applying the duplicate policy
+fn push_part(
+    cfg: &PartsConfig,
+    parts: &mut Vec<(String, Part)>,
+    name: String,
+    part: Part,
+) -> Result<(), Error> {
+    match part {
+        Part::Skipped => return Ok(()),
+        Part::Failed(_) => {
+            parts.push((name, part));
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    if parts.iter().any(|(k, _)| *k == name) {
+        match cfg.duplicate_policy.unwrap_or(DuplicatePolicy::KeepAll) {
+            DuplicatePolicy::KeepAll => {}
+            DuplicatePolicy::KeepFirst => return Ok(()),
+            DuplicatePolicy::KeepLast => parts.retain(|(k, _)| *k != name),
+            DuplicatePolicy::Reject => {
+                let part = cfg.fail_part(Error::DuplicateField { name: name.clone() })?;
+                parts.push((name, part));
+                return Ok(());
+            }
+        }
+    }
+
+    parts.push((name, part));
+    Ok(())
+}
+
+/// Check that every required field is present in the collected parts
+fn check_required_fields(cfg: &PartsConfig, parts: &Parts) -> Result<(), Error> {
+    let missing = cfg
+        .required_fields
+        .iter()
+        .flat_map(|x| x.iter())
+        .filter(|name| {
+            !parts.texts.0.iter().any(|(k, _)| cfg.matches_field(name, k))
+                && !parts.files.0.iter().any(|(k, v)| cfg.matches_field(name, k) && v.is_ok())
+                && !parts.discarded_files.iter().any(|(k, _)| cfg.matches_field(name, k))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::MissingFields { fields: missing })
+    }
+}
+
+/// How to handle file fields that are not allowed by the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum DisallowedFilePolicy {
+    /// Drain the field without storing it, and record an error in `FileParts`
+    Record,
+    /// Fail the request with 415 Unsupported Media Type
+    Reject,
+}
+
+/// Check a file field against the configured allow and deny lists for content types and
+/// extensions
+fn check_file_allowed(
+    cfg: &PartsConfig,
+    name: &str,
+    mime_type: &mime::Mime,
+    file_name: Option<&str>,
+) -> Result<(), Error> {
+    let listed = |types: Option<&Arc<[mime::Mime]>>| {
+        types.map(|x| x.iter().any(|x| mime_match(x, mime_type)))
+    };
+
+    let allowed = listed(cfg.allowed_mime_types.as_ref()).unwrap_or(true);
+    let denied = listed(cfg.denied_mime_types.as_ref()).unwrap_or(false);
+
+    if !allowed || denied {
+        return Err(Error::DisallowedMimeType {
+            field: name.into(),
+            mime_type: mime_type.to_string(),
+            file_name: file_name.map(String::from),
+        });
+    }
+
+    if let Some(ref extensions) = cfg.allowed_extensions {
+        let extension = match file_name {
+            Some(s) => Path::new(&sanitize_filename::sanitize(s))
+                .extension()
+                .and_then(|x| x.to_str())
+                .map(String::from),
+            None => mime_guess::get_mime_extensions(mime_type)
+                .and_then(|x| x.first())
+                .map(|x| x.to_string()),
+        };
+
+        let allowed = extension
+            .as_deref()
+            .is_some_and(|x| extensions.iter().any(|y| y.eq_ignore_ascii_case(x)));
+
+        if !allowed {
+            return Err(Error::DisallowedExtension {
+                field: name.into(),
+                extension,
+                file_name: file_name.map(String::from),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Check a field that is about to be stored as a file, including a text field spilling over
+/// `with_text_limit`, against the file policies, counting it toward the file limits
+///
+/// Returns `Some` if the field should be drained instead of stored: with an error to record for
+/// a disallowed file, or `Ok` with `with_discard_files`.
+fn admit_file(
+    cfg: &PartsConfig,
+    req_state: &mut RequestState,
+    name: &str,
+    mime_type: &mime::Mime,
+    file_name: Option<&str>,
+) -> Result<Option<Result<(), Error>>, Error> {
+    match check_file_allowed(cfg, name, mime_type, file_name) {
+        Err(e) if cfg.rejects_disallowed_files() => return Err(e),
+        Err(e) => return Ok(Some(Err(e))),
+        Ok(()) => {}
+    }
+
+    req_state.add_file(cfg)?;
+    req_state.add_file_type(cfg, mime_type)?;
+
+    Ok(if cfg.discard_files { Some(Ok(())) } else { None })
+}
+
+/// The part for a field drained on the word of `admit_file`
+fn drained_file_part(
+    res: Result<(), Error>,
+    file_name: Option<String>,
+    content_type: mime::Mime,
+    size: usize,
+) -> Part {
+    match res {
+        Ok(()) => Part::Discarded(FileMetadata { file_name, content_type, size }),
+        Err(e) => Part::File(Err(e)),
+    }
+}
+
+/// Copy the headers sent with a field, lowercasing their names
+fn part_headers<'a>(headers: impl Iterator<Item = (&'a str, &'a [u8])>) -> Vec<(String, Bytes)> {
+    headers.map(|(k, v)| (k.to_ascii_lowercase(), Bytes::copy_from_slice(v))).collect()
+}
+
+/// How many of a file's first bytes are kept to sniff its content type
+#[cfg(feature = "infer")]
+const FILE_HEAD_LEN: usize = 8192;
+
+/// What is learned from the bytes written to a tempfile as they are written: its first bytes,
+/// to sniff its content type with the `infer` feature, and its SHA-256 with the `digest` feature
+#[derive(Default)]
+pub(crate) struct FileTracker {
+    head: Vec<u8>,
+    #[cfg(feature = "digest")]
+    sha256: sha2::Sha256,
+}
+
+impl FileTracker {
+    #[cfg_attr(not(any(feature = "infer", feature = "digest")), allow(unused_variables))]
+    fn push(&mut self, data: &[u8]) {
+        #[cfg(feature = "infer")]
+        {
+            let n = FILE_HEAD_LEN.saturating_sub(self.head.len()).min(data.len());
+            self.head.extend_from_slice(&data[..n]);
+        }
+        #[cfg(feature = "digest")]
+        sha2::Digest::update(&mut self.sha256, data);
+    }
+
+    fn content_type(&self) -> Option<mime::Mime> {
+        sniff_content_type(&self.head)
+    }
+
+    #[cfg(feature = "digest")]
+    fn sha256(&self) -> Option<[u8; 32]> {
+        Some(sha2::Digest::finalize(self.sha256.clone()).into())
+    }
+
+    #[cfg(not(feature = "digest"))]
+    fn sha256(&self) -> Option<[u8; 32]> {
+        None
+    }
+}
+
+/// The content type recognized from a file's first bytes
+#[cfg(feature = "infer")]
+fn sniff_content_type(data: &[u8]) -> Option<mime::Mime> {
+    infer::get(data).and_then(|x| x.mime_type().parse().ok())
+}
+
+#[cfg(not(feature = "infer"))]
+fn sniff_content_type(_: &[u8]) -> Option<mime::Mime> {
+    None
+}
+
+/// Whether the `Content-Transfer-Encoding` header sent with a field, if any, is base64
+fn is_base64_encoded(header: Option<&[u8]>) -> bool {
+    header
+        .and_then(|x| std::str::from_utf8(x).ok())
+        .is_some_and(|x| x.trim().eq_ignore_ascii_case("base64"))
+}
+
+/// Parse the `Content-Type` header sent with a field, if any, ignoring one that cannot be parsed
+/// unless `strict_content_types` is set
+fn declared_content_type(
+    cfg: &PartsConfig,
+    name: &str,
+    header: Option<&[u8]>,
+) -> Result<Option<mime::Mime>, Error> {
+    let header = match header {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    match std::str::from_utf8(header).ok().and_then(|x| x.parse().ok()) {
+        Some(x) => Ok(Some(x)),
+        None if cfg.strict_content_types => Err(Error::InvalidContentType {
+            field: name.into(),
+            got: String::from_utf8_lossy(header).into_owned(),
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Check that a request's `Content-Type` is multipart with a boundary, before reading its body
+fn check_request_content_type(header: Option<&[u8]>) -> Result<(), Error> {
+    let not_multipart = || Error::NotMultipart {
+        content_type: header.map(|x| String::from_utf8_lossy(x).into_owned()),
+    };
+
+    let mime_type = header
+        .and_then(|x| std::str::from_utf8(x).ok())
+        .and_then(|x| x.parse::<mime::Mime>().ok())
+        .ok_or_else(not_multipart)?;
+
+    if mime_type.type_() != mime::MULTIPART {
+        return Err(not_multipart());
+    }
+
+    match mime_type.get_param(mime::BOUNDARY) {
+        Some(x) if !x.as_str().is_empty() => Ok(()),
+        _ => Err(Error::MissingBoundary),
+    }
+}
+
+/// Apply the configured length limits to a field name and filename
+fn limit_name_lens(
+    cfg: &PartsConfig,
+    name: String,
+    file_name: Option<String>,
+) -> Result<(String, Option<String>), Error> {
+    let policy = cfg.long_name_policy.unwrap_or(LongNamePolicy::Reject);
+
+    let limit_len = |s: String, limit: Option<usize>| match limit {
+        Some(limit) if s.len() > limit => match policy {
+            LongNamePolicy::Truncate => {
+                let end = (0..=limit).rev().find(|i| s.is_char_boundary(*i)).unwrap_or(0);
+                Ok(s[..end].to_string())
+            }
+            LongNamePolicy::Reject => Err(Error::NameTooLong { limit }),
+        },
+        _ => Ok(s),
+    };
+
+    let name = limit_len(name, cfg.max_field_name_len)?;
+    let file_name = match file_name {
+        Some(s) => Some(limit_len(s, cfg.max_file_name_len)?),
+        None => None,
+    };
+
+    Ok((name, file_name))
+}
+
+/// The path to hand to the OS when persisting
+///
+/// On Windows, absolute paths are spelled in their verbatim (`\\?\` or `\\?\UNC\`) form so
+/// that long paths do not fail with `MAX_PATH` errors.
+fn os_path(path: &Path) -> std::borrow::Cow<'_, Path> {
+    #[cfg(windows)]
+    {
+        if let Some(s) = path.to_str().and_then(verbatim_path) {
+            return std::borrow::Cow::Owned(PathBuf::from(s));
+        }
+    }
+
+    std::borrow::Cow::Borrowed(path)
+}
+
+/// The verbatim form of an absolute Windows path, if it has one and is not already verbatim
+///
+/// Verbatim paths skip normalization, so separators are converted to `\` and paths with
+/// `.` or `..` components are left alone.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn verbatim_path(path: &str) -> Option<String> {
+    let path = path.replace('/', "\\");
+
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        return None;
+    }
+
+    if path.split('\\').any(|x| x == "." || x == "..") {
+        return None;
+    }
+
+    if let Some(unc) = path.strip_prefix(r"\\") {
+        return Some(format!(r"\\?\UNC\{}", unc));
+    }
+
+    let bytes = path.as_bytes();
+    if bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && &bytes[1..3] == b":\\" {
+        return Some(format!(r"\\?\{}", path));
+    }
+
+    None
+}
+
+/// A tempfile builder with the configured name prefix and suffix, and permissions on Unix
+fn temp_file_builder(cfg: &PartsConfig) -> tempfile::Builder<'_, '_> {
+    let mut builder = tempfile::Builder::new();
+    if let Some(ref prefix) = cfg.temp_file_prefix {
+        builder.prefix(&**prefix);
+    }
+    if let Some(ref suffix) = cfg.temp_file_suffix {
+        builder.suffix(&**suffix);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Some(mode) = cfg.temp_file_mode {
+            builder.permissions(std::fs::Permissions::from_mode(mode));
+        }
+    }
+    builder
+}
+
+/// Tmp directories that `PartsConfig::validate` has created a tempfile in
+static CHECKED_TEMP_DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+fn temp_dir_checked(dir: &Path) -> bool {
+    CHECKED_TEMP_DIRS.lock().is_ok_and(|dirs| dirs.iter().any(|x| x == dir))
+}
+
+fn remember_temp_dir(dir: &Path) {
+    if let Ok(mut dirs) = CHECKED_TEMP_DIRS.lock() {
+        dirs.push(dir.to_path_buf());
+    }
+}
+
+/// Create a tempfile in `dir`, creating the directory first if it is missing and configured to
+fn temp_file_in(cfg: &PartsConfig, dir: &Path) -> std::io::Result<NamedTempFile> {
+    match temp_file_builder(cfg).tempfile_in(dir) {
+        Err(ref e) if cfg.create_temp_dir && e.kind() == std::io::ErrorKind::NotFound => {
+            let mut dir_builder = std::fs::DirBuilder::new();
+            dir_builder.recursive(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::DirBuilderExt;
+                if let Some(mode) = cfg.temp_dir_mode {
+                    dir_builder.mode(mode);
+                }
+            }
+            dir_builder.create(dir)?;
+            temp_file_builder(cfg).tempfile_in(dir)
+        }
+        x => x,
+    }
+}
+
+/// Check that the filesystem of a tempfile has the configured free space left, failing with
+/// `Error::InsufficientStorage` inside an `io::Error`
+#[cfg(feature = "fs2")]
+fn check_free_space(cfg: &PartsConfig, file: &NamedTempFile) -> std::io::Result<()> {
+    let min_free_space = match cfg.min_free_space {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    if fs2::available_space(file.path())? < min_free_space as u64 {
+        let e = Error::InsufficientStorage { min_free_space };
+        return Err(std::io::Error::other(e));
+    }
+
+    Ok(())
+}
+
+/// Convert an error from creating or writing a tempfile, unwrapping an `Error` (e.g.,
+/// `Error::InsufficientStorage`) carried inside it and telling a full disk apart
+fn temp_file_error(e: std::io::Error) -> Error {
+    match e.get_ref().map(|x| x.is::<Error>()) {
+        Some(true) => *e.into_inner().and_then(|x| x.downcast().ok()).expect("Checked above"),
+        _ if is_storage_exhausted(&e) => Error::StorageExhausted { source: e },
+        _ => Error::Io(e),
+    }
+}
+
+/// Whether an I/O error means the disk or the user's quota is full
+#[cfg(unix)]
+fn is_storage_exhausted(e: &std::io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::ENOSPC) | Some(libc::EDQUOT))
+}
+
+/// Whether an I/O error means the disk is full
+#[cfg(windows)]
+fn is_storage_exhausted(e: &std::io::Error) -> bool {
+    const ERROR_HANDLE_DISK_FULL: i32 = 39;
+    const ERROR_DISK_FULL: i32 = 112;
+
+    matches!(e.raw_os_error(), Some(ERROR_HANDLE_DISK_FULL) | Some(ERROR_DISK_FULL))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_storage_exhausted(_: &std::io::Error) -> bool {
+    false
+}
+
+/// An error for blocking work that could not be run, e.g. once the thread pool has gone
+fn blocking_error<E: std::fmt::Display>(e: E) -> Error {
+    Error::Io(std::io::Error::other(e.to_string()))
+}
+
+/// Create a tempfile in the tmp directory for a file of `len` bytes
+fn create_temp_file(cfg: &PartsConfig, len: usize) -> std::io::Result<NamedTempFile> {
+    let file = match cfg.temp_dir_for(len) {
+        Some(temp_dir) => temp_file_in(cfg, temp_dir)?,
+        _ => temp_file_builder(cfg).tempfile()?,
+    };
+
+    #[cfg(feature = "fs2")]
+    check_free_space(cfg, &file)?;
+
+    Ok(file)
+}
+
+/// Move a tempfile to the tmp directory for its new size, if it has outgrown its current tier
+///
+/// The file is linked into the new directory under a fresh name, keeping its handle, and only
+/// copied if that fails (e.g., across filesystems). This is called before each write, so it
+/// also checks the free space.
+fn retier_temp_file(
+    cfg: &PartsConfig,
+    file: NamedTempFile,
+    len: usize,
+) -> std::io::Result<NamedTempFile> {
+    #[cfg(feature = "fs2")]
+    check_free_space(cfg, &file)?;
+
+    if cfg.temp_dir_tiers.is_none() {
+        return Ok(file);
+    }
+
+    let dir = cfg.temp_dir_for(len).map(Path::to_path_buf).unwrap_or_else(std::env::temp_dir);
+
+    if file.path().parent() == Some(dir.as_path()) {
+        return Ok(file);
+    }
+
+    let (handle, old_path) = file.into_parts();
+    let mut handle = Some(handle);
+    let linked = temp_file_builder(cfg).make_in(&dir, |path| {
+        std::fs::hard_link(&old_path, path)?;
+        Ok(handle.take().expect("Linked once"))
+    });
+    let mut file = match (linked, handle) {
+        (Ok(moved), _) => return Ok(moved),
+        (Err(_), Some(handle)) => NamedTempFile::from_parts(handle, old_path),
+        (Err(e), None) => return Err(e),
+    };
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut moved = temp_file_in(cfg, &dir)?;
+    std::io::copy(&mut file, &mut moved)?;
+    Ok(moved)
+}
+
+#[derive(Debug)]
+enum Buffer {
+    Cursor(Cursor<Vec<u8>>),
+    File(NamedTempFile),
+    Memory(Vec<u8>),
+}
+
+struct FileTooLarge {
+    limit: usize,
+    received: usize,
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use tempfile::NamedTempFile;
+
+    use crate::{
+        check_file_allowed, check_request_content_type, check_required_fields, classify_field,
+        create_temp_file, declared_content_type, file_chunk_len, glob_match, limit_name_lens,
+        name_with_suffix, parse_size, push_part, retier_temp_file, temp_file_error, verbatim_path,
+        CollisionPolicy, DuplicatePolicy, Error, ErrorMode, FieldAction, FieldKind, File,
+        FileMetadata, FileMix, FileParts, LongNamePolicy, OversizePolicy, Part, Parts, PartsConfig,
+        RequestState, TextOverflowPolicy, TextParts,
+    };
+    use std::{io::Write, iter, path::Path};
+
+    fn mem_file(x: &'static str) -> Result<File, Error> {
+        Ok(File::new_in_memory(Bytes::from(x), None, None))
+    }
+
+    #[test]
+    pub fn create_file_with_size() {
+        // ARRANGE
+        let file_name = "my name jeff.txt".to_string();
+        let sanitized_file_name = sanitize_filename::sanitize(file_name.clone());
+
+        let size = 20usize;
+        let expected_content = iter::repeat_n(1u8, size).collect::<Vec<_>>();
+
+        let mut name_tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        name_tempfile.write_all(&expected_content).expect("Failed writing to file.");
+
+        // ACT
+        let file = File::new_with_file_name(name_tempfile, file_name.clone());
+        let tempfile_path = file.as_temp_file().unwrap().path().to_path_buf();
+        let content = std::fs::read(tempfile_path).expect("Can not read temporary file");
+
+        // ASSERT
+        assert_eq!(expected_content, content);
+        assert_eq!(file.original_file_name, Some(file_name));
+        assert_eq!(file.sanitized_file_name, sanitized_file_name);
+    }
+
+    #[test]
+    pub fn create_file_then_persist() {
+        // ARRANGE
+        let file_name = "create_file_then_persist.txt".to_string();
+        let file_path = std::env::temp_dir();
+
+        let size = 20usize;
+        let expected_content = iter::repeat_n(1u8, size).collect::<Vec<_>>();
+
+        let mut tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        tempfile.write_all(&expected_content).expect("Failed writing to file.");
+
+        // ACT
+        let file = File::new_with_file_name(tempfile, file_name.clone());
+        file.persist_in(&file_path).expect("Failed persisting file");
+
+        // ASSERT
+        let content = std::fs::read(format!("{}/{}", file_path.to_string_lossy(), file_name))
+            .expect("Can not read temporary file");
+
+        assert_eq!(expected_content, content);
+    }
+
+    #[test]
+    pub fn in_memory_file_then_persist() {
+        let dir = tempfile::tempdir().expect("Failed creating temp dir.");
+        let file = File::new_in_memory(Bytes::from("Hello"), Some("hello.txt".into()), None);
+
+        assert_eq!(file.bytes(), Some(&Bytes::from("Hello")));
+        assert!(file.as_temp_file().is_none());
+
+        let path = file.persist_in(dir.path()).expect("Failed persisting file.");
+        let content = std::fs::read(path).expect("Can not read persisted file");
+
+        assert_eq!(content, b"Hello");
+
+        let file = File::new_in_memory(Bytes::from("Hello"), None, None);
+        let tempfile = file.into_temp_file().expect("Failed writing tempfile.");
+        assert_eq!(std::fs::read(tempfile.path()).unwrap(), b"Hello");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn files_are_persisted_with_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().expect("Failed creating temp dir.");
+        let tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        let disk = File::new_with_file_name(tempfile, "a.txt".into());
+        let memory = File::new_in_memory(Bytes::from("Hello"), Some("b.txt".into()), None);
+
+        for file in [disk, memory] {
+            let path = file.persist_with_permissions(dir.path(), 0o600).unwrap();
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    pub fn temp_files_are_named_with_prefix_and_suffix() {
+        let cfg =
+            PartsConfig::default().with_temp_file_prefix("awmp-").with_temp_file_suffix(".upload");
+
+        let file = create_temp_file(&cfg, 0).expect("Failed creating temp file.");
+        let name = file.path().file_name().unwrap().to_string_lossy().into_owned();
+
+        assert!(name.starts_with("awmp-"));
+        assert!(name.ends_with(".upload"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn temp_files_are_created_with_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let cfg = PartsConfig::default().with_temp_file_mode(0o600);
+
+        let file = create_temp_file(&cfg, 0).expect("Failed creating temp file.");
+        let mode =
+            file.as_file().metadata().expect("Failed reading metadata.").permissions().mode();
+
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    pub fn in_memory_budget_rejects_requests() {
+        let cfg = PartsConfig::unlimited().with_in_memory_only(10);
+        let mut req_state = RequestState::default();
+
+        assert!(req_state.add_bytes(&cfg, 8).is_ok());
+        assert!(matches!(req_state.add_bytes(&cfg, 4), Err(Error::RequestTooLarge { limit: 10 })));
+    }
+
+    #[test]
+    pub fn default_config_is_recommended() {
+        let cfg = PartsConfig::unlimited();
+        assert_eq!((cfg.text_limit, cfg.file_limit, cfg.max_files), (None, None, None));
+
+        let cfg = PartsConfig::default();
+        assert_eq!(cfg.text_limit, Some(64 * 1024));
+        assert_eq!(cfg.file_limit, Some(10 * 1024 * 1024));
+        assert_eq!(cfg.max_files, Some(20));
+    }
+
+    #[test]
+    pub fn memory_threshold_keeps_small_files_in_memory() {
+        let cfg = PartsConfig::default();
+        assert!(!cfg.in_memory_for(0));
+
+        let cfg = cfg.with_memory_threshold(10);
+        assert!(cfg.in_memory_for(10));
+        assert!(!cfg.in_memory_for(11));
+
+        let cfg = cfg.with_in_memory_only(100);
+        assert!(cfg.in_memory_for(50));
+    }
+
+    #[test]
+    pub fn tiered_temp_dirs_pick_largest_matching_threshold() {
+        let cfg = PartsConfig::default()
+            .with_temp_dir("/fast")
+            .with_tiered_temp_dirs(vec![(1000, "/big"), (10, "/medium")]);
+
+        assert_eq!(cfg.temp_dir_for(5), Some(Path::new("/fast")));
+        assert_eq!(cfg.temp_dir_for(50), Some(Path::new("/medium")));
+        assert_eq!(cfg.temp_dir_for(5000), Some(Path::new("/big")));
+    }
+
+    #[test]
+    pub fn temp_files_move_to_the_next_tier() {
+        let (small, big) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let cfg = PartsConfig::default()
+            .with_temp_dir(small.path())
+            .with_tiered_temp_dirs(vec![(10, big.path())]);
+
+        let mut file = create_temp_file(&cfg, 0).expect("Failed creating tempfile");
+        file.write_all(b"Hello").unwrap();
+        let old_path = file.path().to_path_buf();
+
+        let file = retier_temp_file(&cfg, file, 5).expect("Failed retiering tempfile");
+        assert_eq!(file.path(), old_path);
+
+        let file = retier_temp_file(&cfg, file, 20).expect("Failed retiering tempfile");
+        assert_eq!(file.path().parent(), Some(big.path()));
+        assert!(!old_path.exists());
+        assert_eq!(std::fs::read(file.path()).unwrap(), b"Hello");
+    }
+
+    #[test]
+    pub fn temp_dir_is_mapped_per_field() {
+        let cfg = PartsConfig::default()
+            .with_temp_dir("/fast")
+            .with_tiered_temp_dirs(vec![(100, "/big")])
+            .with_temp_dir_for("video*", "/mnt/bigdisk");
+
+        assert!(cfg.for_field("image").is_none());
+
+        let video_cfg = cfg.for_field("video_1").expect("Field not mapped");
+        assert_eq!(video_cfg.temp_dir_for(5), Some(Path::new("/mnt/bigdisk")));
+        assert_eq!(video_cfg.temp_dir_for(5000), Some(Path::new("/mnt/bigdisk")));
+
+        assert!(cfg.for_field("Video_1").is_none());
+        assert!(cfg.with_case_insensitive_fields(true).for_field("Video_1").is_some());
+    }
+
+    #[test]
+    pub fn field_kinds_for_mixed_parts() {
+        let tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        let parts = Parts {
+            texts: TextParts::from(vec![("title".into(), "Hello".into())]),
+            files: FileParts::from(vec![(
+                "upload".into(),
+                Ok(File::new_with_file_name(tempfile, "hello.txt".into())),
+            )]),
+            discarded_files: Vec::new(),
+            failed: Vec::new(),
+        };
+
+        assert_eq!(parts.len(), 2);
+        assert!(!parts.is_empty());
+        assert_eq!(parts.field_kinds().get("title"), Some(&FieldKind::Text));
+        assert_eq!(parts.field_kinds().get("upload"), Some(&FieldKind::File));
+        assert!(parts.errors().is_empty());
+    }
+
+    #[test]
+    pub fn part_errors_are_collected() {
+        let tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        let parts = vec![
+            ("title".to_string(), Part::Text("Hello".into(), None)),
+            ("upload".to_string(), Part::File(Ok(File::new(tempfile, None, None)))),
+            (
+                "big".to_string(),
+                Part::File(Err(Error::FileTooLarge {
+                    field: "big".into(),
+                    limit: 10,
+                    received: 11,
+                    file_name: None,
+                })),
+            ),
+        ]
+        .into_iter()
+        .collect::<Parts>();
+
+        assert!(parts.files.first("upload").is_some());
+        assert_eq!(parts.errors().len(), 1);
+        assert_eq!(parts.errors()[0].0, "big");
+        assert!(matches!(parts.errors()[0].1, Error::FileTooLarge { limit: 10, .. }));
+    }
+
+    #[test]
+    pub fn mime_types_are_allowed_and_denied() {
+        let cfg = PartsConfig::default()
+            .with_allowed_mime_types(vec![mime::IMAGE_STAR, mime::APPLICATION_PDF])
+            .with_denied_mime_types(vec![mime::IMAGE_SVG]);
+
+        assert!(check_file_allowed(&cfg, "a", &mime::IMAGE_PNG, None).is_ok());
+        assert!(check_file_allowed(&cfg, "a", &mime::APPLICATION_PDF, None).is_ok());
+        assert!(check_file_allowed(&cfg, "a", &mime::IMAGE_SVG, None).is_err());
+        assert!(check_file_allowed(&cfg, "a", &mime::TEXT_HTML, Some("a.html")).is_err());
+        assert!(check_file_allowed(&PartsConfig::default(), "a", &mime::TEXT_HTML, None).is_ok());
+    }
+
+    #[test]
+    pub fn text_overflow_can_be_an_error() {
+        let cfg = PartsConfig::default().with_text_limit(10);
+        assert!(cfg.check_text_overflow("title").is_ok());
+
+        let cfg = cfg.with_text_overflow_policy(TextOverflowPolicy::Error);
+        match cfg.check_text_overflow("title") {
+            Err(Error::TextTooLarge { limit, name }) => {
+                assert_eq!((limit, name.as_str()), (10, "title"))
+            }
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    pub fn oversize_files_are_truncated_or_discarded() {
+        let cfg = PartsConfig::default().with_file_limit(10);
+
+        assert_eq!(file_chunk_len(&cfg, 4, 8), Ok(4));
+        assert_eq!(file_chunk_len(&cfg, 4, 12), Err(10));
+
+        let cfg = cfg.with_oversize_policy(OversizePolicy::Truncate);
+
+        assert_eq!(file_chunk_len(&cfg, 4, 12), Ok(2));
+        assert_eq!(file_chunk_len(&cfg, 4, 16), Ok(0));
+    }
+
+    #[test]
+    pub fn discarded_files_are_collected_as_metadata() {
+        let metadata = FileMetadata {
+            file_name: Some("a.png".into()),
+            content_type: mime::IMAGE_PNG,
+            size: 20,
+        };
+
+        let parts = vec![
+            ("title".to_string(), Part::Text(Bytes::from("Hello"), None)),
+            ("upload".to_string(), Part::Discarded(metadata)),
+        ]
+        .into_iter()
+        .collect::<Parts>();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts.discarded_files[0].0, "upload");
+        assert_eq!(parts.discarded_files[0].1.size, 20);
+        assert_eq!(parts.field_kinds().get("upload"), Some(&FieldKind::File));
+    }
+
+    #[test]
+    pub fn duplicate_policy_is_applied_per_name() {
+        let collect = |policy: DuplicatePolicy| -> Result<Parts, Error> {
+            let cfg = PartsConfig::default().with_duplicate_policy(policy);
+            let mut parts = Vec::new();
+            for (name, val) in [("a", "1"), ("b", "2"), ("a", "3")] {
+                push_part(&cfg, &mut parts, name.into(), Part::Text(Bytes::from(val), None))?;
+            }
+            Ok(parts.into_iter().collect::<Parts>())
+        };
+
+        let parts = collect(DuplicatePolicy::KeepFirst);
+        assert_eq!(parts.unwrap().texts.as_pairs(), vec![("a", "1"), ("b", "2")]);
+
+        let parts = collect(DuplicatePolicy::KeepLast);
+        assert_eq!(parts.unwrap().texts.as_pairs(), vec![("b", "2"), ("a", "3")]);
+
+        let parts = collect(DuplicatePolicy::KeepAll);
+        assert_eq!(parts.unwrap().texts.as_pairs(), vec![("a", "1"), ("b", "2"), ("a", "3")]);
+
+        assert!(collect(DuplicatePolicy::Reject).is_err());
+    }
+
+    #[test]
+    pub fn part_errors_are_collected_in_collect_mode() {
+        let cfg = PartsConfig::default()
+            .with_duplicate_policy(DuplicatePolicy::Reject)
+            .with_oversize_policy(OversizePolicy::RejectRequest)
+            .with_error_mode(ErrorMode::Collect);
+
+        let mut parts = Vec::new();
+        for (name, val) in [("a", "1"), ("a", "2")] {
+            push_part(&cfg, &mut parts, name.into(), Part::Text(Bytes::from(val), None)).unwrap();
+        }
+        let e = Error::TextTooLarge { limit: 10, name: "b".into() };
+        let part = cfg.fail_part(e).unwrap();
+        push_part(&cfg, &mut parts, "b".into(), part).unwrap();
+
+        let parts = parts.into_iter().collect::<Parts>();
+        assert_eq!(parts.texts.as_pairs(), vec![("a", "1")]);
+        assert_eq!(parts.failed.len(), 2);
+        assert!(matches!(parts.errors()[0], ("a", Error::DuplicateField { .. })));
+        assert!(matches!(parts.errors()[1], ("b", Error::TextTooLarge { .. })));
+        assert!(!cfg.rejects_oversize_files());
+
+        let cfg = cfg.with_error_mode(ErrorMode::FailFast);
+        assert!(cfg.fail_part(Error::FileMixNotAllowed).is_err());
+        assert!(cfg.rejects_oversize_files());
+    }
+
+    #[test]
+    pub fn required_fields_are_reported_missing() {
+        let parts = Parts {
+            texts: TextParts::from(vec![("title".into(), "Hello".into())]),
+            files: FileParts::from(vec![(
+                "file".into(),
+                Err(Error::FileTooLarge {
+                    field: "file".into(),
+                    limit: 1,
+                    received: 2,
+                    file_name: None,
+                }),
+            )]),
+            discarded_files: Vec::new(),
+            failed: Vec::new(),
+        };
+
+        let cfg = PartsConfig::default().with_required_fields(vec!["title".into()]);
+        assert!(check_required_fields(&cfg, &parts).is_ok());
+
+        let cfg = cfg.with_required_fields(vec!["title".into(), "file".into(), "tags".into()]);
+        match check_required_fields(&cfg, &parts) {
+            Err(Error::MissingFields { fields }) => assert_eq!(fields, vec!["file", "tags"]),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+
+        let cfg = PartsConfig::default().with_required_fields(vec!["Title".into()]);
+        assert!(check_required_fields(&cfg, &parts).is_err());
+        assert!(check_required_fields(&cfg.with_case_insensitive_fields(true), &parts).is_ok());
+    }
+
+    #[test]
+    pub fn extensions_are_allowed() {
+        let cfg = PartsConfig::default().with_allowed_extensions(vec!["pdf".into(), "docx".into()]);
+
+        assert!(check_file_allowed(&cfg, "a", &mime::APPLICATION_PDF, Some("report.PDF")).is_ok());
+        assert!(check_file_allowed(&cfg, "a", &mime::APPLICATION_PDF, None).is_ok());
+        assert!(check_file_allowed(&cfg, "a", &mime::TEXT_PLAIN, Some("notes.docx")).is_ok());
+        assert!(check_file_allowed(&cfg, "a", &mime::APPLICATION_PDF, Some("run.exe")).is_err());
+        assert!(check_file_allowed(&cfg, "a", &mime::APPLICATION_PDF, Some("README")).is_err());
+    }
+
+    #[test]
+    pub fn file_mix_allows_any_alternative() {
+        let video = "video/mp4".parse::<mime::Mime>().unwrap();
+        let mix = FileMix::new()
+            .or(vec![(mime::IMAGE_STAR, 2)])
+            .or(vec![("video/*".parse().unwrap(), 1)]);
+
+        assert!(mix.allows(&[mime::IMAGE_PNG, mime::IMAGE_JPEG]));
+        assert!(mix.allows(std::slice::from_ref(&video)));
+        assert!(!mix.allows(&[mime::IMAGE_PNG, mime::IMAGE_PNG, mime::IMAGE_PNG]));
+        assert!(!mix.allows(&[mime::IMAGE_PNG, video.clone()]));
+        assert!(!mix.allows(&[video.clone(), video]));
+        assert!(!mix.allows(&[mime::APPLICATION_PDF]));
+    }
+
+    #[test]
+    pub fn long_names_are_truncated_on_char_boundaries() {
+        let cfg = PartsConfig::default()
+            .with_max_field_name_len(4)
+            .with_max_file_name_len(6)
+            .with_long_name_policy(LongNamePolicy::Truncate);
+
+        let (name, file_name) =
+            limit_name_lens(&cfg, "upload".into(), Some("héllo.txt".into())).unwrap();
+
+        assert_eq!(name, "uplo");
+        assert_eq!(file_name.as_deref(), Some("héllo"));
+
+        let cfg = cfg.with_long_name_policy(LongNamePolicy::Reject);
+        assert!(limit_name_lens(&cfg, "upload".into(), None).is_err());
+    }
+
+    #[test]
+    pub fn text_values_are_looked_up_by_name() {
+        let texts = TextParts::from(vec![
+            ("b".into(), "1".into()),
+            ("a".into(), Bytes::from(&[0xff][..])),
+            ("b".into(), "3".into()),
+            ("a".into(), "2".into()),
+        ]);
+
+        assert_eq!(texts.get("b"), Some("1"));
+        assert_eq!(texts.get_all("b"), vec!["1", "3"]);
+        assert_eq!(texts.get("a"), None);
+        assert_eq!(texts.get_all("a"), vec!["2"]);
+        assert!(texts.get_all("c").is_empty());
+    }
+
+    #[test]
+    pub fn text_values_are_taken_by_name() {
+        let mut texts = TextParts::from(vec![
+            ("b".into(), "1".into()),
+            ("a".into(), "2".into()),
+            ("b".into(), "3".into()),
+        ]);
+
+        assert_eq!(texts.take("b"), vec![Bytes::from("1"), Bytes::from("3")]);
+        assert_eq!(texts.as_pairs(), vec![("a", "2")]);
+        assert!(texts.take("b").is_empty());
+    }
+
+    #[test]
+    pub fn text_values_are_parsed() {
+        let texts = TextParts::from(vec![("n".into(), "42".into()), ("x".into(), "abc".into())]);
+
+        assert_eq!(texts.parse::<u32>("n").unwrap(), 42);
+        assert!(matches!(texts.parse::<u32>("m"), Err(Error::MissingFields { .. })));
+        match texts.parse::<u32>("x") {
+            Err(Error::InvalidValue { field, .. }) => assert_eq!(field, "x"),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    pub fn lossy_pairs_keep_non_utf8_values() {
+        let texts = TextParts::from(vec![
+            ("a".into(), "1".into()),
+            ("b".into(), Bytes::from(&b"x\xffy"[..])),
+        ]);
+
+        assert_eq!(texts.as_pairs(), vec![("a", "1")]);
+        assert_eq!(
+            texts.as_pairs_lossy(),
+            vec![
+                ("a", std::borrow::Cow::Borrowed("1")),
+                ("b", std::borrow::Cow::Owned("x\u{fffd}y".into()))
+            ]
+        );
+    }
+
+    #[cfg(feature = "encoding_rs")]
+    #[test]
+    pub fn text_values_are_decoded_as_given_encoding() {
+        let texts = TextParts::from(vec![("name".into(), Bytes::from(&b"Jos\xe9"[..]))]);
+
+        assert_eq!(texts.get("name"), None);
+        assert_eq!(texts.decode_as("name", encoding_rs::WINDOWS_1252).unwrap(), "José");
+        assert!(texts.decode_as("missing", encoding_rs::WINDOWS_1252).is_none());
+    }
+
+    #[test]
+    pub fn checkbox_values_are_coerced_to_bool() {
+        let texts = TextParts::from(vec![
+            ("a".into(), "on".into()),
+            ("b".into(), " TRUE ".into()),
+            ("c".into(), "0".into()),
+            ("d".into(), "ja".into()),
+        ]);
+
+        assert!(texts.get_bool("a"));
+        assert!(texts.get_bool("b"));
+        assert!(!texts.get_bool("c"));
+        assert!(!texts.get_bool("d"));
+        assert!(!texts.get_bool("missing"));
+        assert!(texts.get_bool_with("d", &["ja"]));
+        assert!(!texts.get_bool_with("a", &["ja"]));
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    pub fn bracketed_names_are_nested_into_json() {
+        let texts = TextParts::from(vec![
+            ("user[name]".into(), "Ann".into()),
+            ("user[address][city]".into(), "Oslo".into()),
+            ("tags[0]".into(), "a".into()),
+            ("tags[1]".into(), "b".into()),
+            ("ids[]".into(), "1".into()),
+            ("ids[]".into(), "2".into()),
+            ("color".into(), "red".into()),
+            ("color".into(), "blue".into()),
+            ("odd[".into(), "x".into()),
+        ]);
+
+        assert_eq!(
+            texts.to_json_value(),
+            serde_json::json!({
+                "user": { "name": "Ann", "address": { "city": "Oslo" } },
+                "tags": ["a", "b"],
+                "ids": ["1", "2"],
+                "color": ["red", "blue"],
+                "odd[": "x",
+            })
+        );
+    }
+
+    #[cfg(all(feature = "serde", feature = "serde_urlencoded"))]
+    #[test]
+    pub fn text_parts_are_deserialized() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        struct Form {
+            title: String,
+            count: u32,
+        }
+
+        let texts =
+            TextParts::from(vec![("title".into(), "Hello".into()), ("count".into(), "3".into())]);
+        assert_eq!(texts.deserialize::<Form>().unwrap(), Form { title: "Hello".into(), count: 3 });
+
+        let texts = TextParts::from(vec![("title".into(), "Hello".into())]);
+        assert!(matches!(texts.deserialize::<Form>(), Err(Error::InvalidForm { .. })));
+    }
+
+    #[test]
+    pub fn multi_maps_keep_repeated_values() {
+        let texts = TextParts::from(vec![
+            ("b".into(), "1".into()),
+            ("a".into(), Bytes::from(&[0xff][..])),
+            ("b".into(), "3".into()),
+        ]);
+
+        let map = texts.as_multi_map();
+        assert_eq!(map["b"], vec!["1", "3"]);
+        assert!(!map.contains_key("a"));
+
+        let map = texts.into_multi_map();
+        assert_eq!(map["b"], vec!["1".to_string(), "3".to_string()]);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    pub fn take_first_leaves_later_files() {
+        let mut files = FileParts::from(vec![
+            ("a".into(), Err(Error::MissingBoundary)),
+            ("a".into(), mem_file("1")),
+            ("b".into(), mem_file("2")),
+            ("a".into(), mem_file("3")),
+        ]);
+
+        assert_eq!(files.take_first("a").unwrap().bytes(), Some(&Bytes::from("1")));
+        assert_eq!(files.take_first("a").unwrap().bytes(), Some(&Bytes::from("3")));
+        assert!(files.take_first("a").is_none());
+        let rest = files.iter_indexed().map(|(i, k, _)| (i, k)).collect::<Vec<_>>();
+        assert_eq!(rest, vec![(0, "a"), (2, "b")]);
+    }
+
+    #[test]
+    pub fn take_all_groups_files_by_name() {
+        let files = FileParts::from(vec![
+            ("a".into(), mem_file("1")),
+            ("b".into(), Err(Error::MissingBoundary)),
+            ("a".into(), mem_file("2")),
+        ]);
+
+        let map = files.take_all();
+        assert_eq!(map.len(), 1);
+        let a = map["a"].iter().map(|x| x.bytes().unwrap().clone()).collect::<Vec<_>>();
+        assert_eq!(a, vec![Bytes::from("1"), Bytes::from("2")]);
+    }
+
+    #[test]
+    pub fn file_counts_leave_out_errors_unless_asked() {
+        let files = FileParts::from(vec![
+            ("b".into(), mem_file("1")),
+            ("a".into(), Err(Error::MissingBoundary)),
+            ("b".into(), mem_file("2")),
+        ]);
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files.len_with_errors(), 3);
+        assert!(!files.is_empty());
+        assert_eq!(files.keys(), vec!["b"]);
+        assert_eq!(files.keys_with_errors(), vec!["b", "a"]);
+
+        let files = FileParts::from(vec![("a".into(), Err(Error::MissingBoundary))]);
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    pub fn file_errors_are_listed_and_taken() {
+        let mut files = FileParts::from(vec![
+            ("a".into(), mem_file("1")),
+            ("b".into(), Err(Error::MissingBoundary)),
+        ]);
+
+        let errors = files.errors().into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(errors, vec!["b"]);
+
+        let errors = files.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "b");
+        assert!(files.errors().is_empty());
+        assert_eq!(files.len_with_errors(), 1);
+    }
+
+    #[test]
+    pub fn file_parts_can_be_iterated() {
+        let files = FileParts::from(vec![("a".into(), mem_file("1")), ("b".into(), mem_file("2"))]);
+
+        let names = (&files).into_iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(files.iter().filter(|(_, v)| v.is_ok()).count(), 2);
+
+        let names = files.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(names, vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    pub fn all_files_are_persisted() {
+        let dir = tempfile::tempdir().expect("Failed creating tmp dir");
+        let file = |x: &'static str, name: &str| {
+            Ok(File::new_in_memory(Bytes::from(x), Some(name.into()), None))
+        };
+        let files = FileParts::from(vec![
+            ("a".into(), file("1", "one.txt")),
+            ("b".into(), Err(Error::MissingBoundary)),
+            ("c".into(), file("3", "../three.txt")),
+        ]);
+
+        let paths = files.persist_all_in(dir.path()).expect("Failed persisting files");
+        let names = paths.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["a", "c"]);
+        assert!(paths.iter().all(|(_, path)| path.parent() == Some(dir.path())));
+        assert_eq!(std::fs::read(&paths[0].1).unwrap(), b"1");
+    }
+
+    #[test]
+    pub fn files_are_retained_and_taken_by_predicate() {
+        let mut files = FileParts::from(vec![
+            ("a".into(), mem_file("")),
+            ("b".into(), Err(Error::MissingBoundary)),
+            ("c".into(), mem_file("3")),
+            ("d".into(), mem_file("4")),
+        ]);
+
+        files.retain(|_, f| f.bytes().is_some_and(|x| !x.is_empty()));
+        assert_eq!(files.keys_with_errors(), vec!["b", "c", "d"]);
+
+        let taken = files.take_if(|k, _| k == "d");
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].0, "d");
+        assert_eq!(files.keys_with_errors(), vec!["b", "c"]);
+    }
+
+    #[test]
+    pub fn files_can_be_modified_in_place() {
+        let mut tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        tempfile.write_all(b"Hello").expect("Failed writing to file.");
+        let mut files = FileParts::from(vec![
+            ("a".into(), Err(Error::MissingBoundary)),
+            ("a".into(), Ok(File::new_with_file_name(tempfile, "hello.txt".into()))),
+        ]);
+
+        assert!(files.get_mut(0).is_none());
+        assert!(files.get_mut(1).is_some());
+        assert!(files.get_mut(2).is_none());
+
+        let tempfile = files.first_mut("a").unwrap().as_temp_file_mut().unwrap();
+        tempfile.as_file_mut().set_len(0).expect("Failed truncating file.");
+        assert_eq!(std::fs::read(files.first("a").unwrap().path().unwrap()).unwrap(), b"");
+        assert!(File::new_in_memory(Bytes::new(), None, None).path().is_none());
+    }
+
+    #[test]
+    pub fn file_results_include_failures() {
+        let mut files = FileParts::from(vec![
+            ("a".into(), Err(Error::MissingBoundary)),
+            ("a".into(), mem_file("1")),
+            ("b".into(), mem_file("2")),
+        ]);
+
+        assert!(files.first("a").is_some());
+        assert!(matches!(files.first_result("a"), Some(Err(Error::MissingBoundary))));
+        assert!(files.first_result("c").is_none());
+
+        let taken = files.take_result("a");
+        assert_eq!(taken.len(), 2);
+        assert!(taken[0].is_err() && taken[1].is_ok());
+        assert_eq!(files.keys_with_errors(), vec!["b"]);
+    }
+
+    #[test]
+    pub fn indexed_files_are_grouped_in_order() {
+        let files = FileParts::from(vec![
+            ("photos[10]".into(), mem_file("10")),
+            ("photos[2]".into(), mem_file("2")),
+            ("photos[x]".into(), mem_file("x")),
+            ("photos".into(), mem_file("none")),
+            ("photos[3]".into(), Err(Error::MissingBoundary)),
+            ("other[1]".into(), mem_file("other")),
+        ]);
+
+        let grouped =
+            files.grouped("photos").into_iter().map(|x| x.bytes().unwrap()).collect::<Vec<_>>();
+        assert_eq!(grouped, vec![&Bytes::from("2"), &Bytes::from("10")]);
+    }
+
+    #[test]
+    pub fn file_sizes_are_summed() {
+        let mut tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        tempfile.write_all(b"Hello").expect("Failed writing to file.");
+        let files = FileParts::from(vec![
+            (
+                "a".into(),
+                Ok(File::new_collected(tempfile, None, &mime::TEXT_PLAIN, 5, &Default::default())),
+            ),
+            ("b".into(), Ok(File::new_in_memory(Bytes::from("abc"), None, None))),
+            ("c".into(), Err(Error::MissingBoundary)),
+        ]);
+
+        assert_eq!(files.first("a").unwrap().size(), 5);
+        assert_eq!(files.first("a").unwrap().content_type(), &mime::TEXT_PLAIN);
+        assert_eq!(files.first("b").unwrap().content_type(), &mime::APPLICATION_OCTET_STREAM);
+        assert_eq!(files.total_size(), 8);
+
+        let tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        assert_eq!(File::new(tempfile, None, None).size(), 0);
+
+        let cfg = PartsConfig::default().with_file_limit(10);
+        assert_eq!(crate::written_file_len(&cfg, 4), 4);
+        assert_eq!(crate::written_file_len(&cfg, 40), 10);
+    }
+
+    #[test]
+    pub fn persisting_handles_name_collisions() {
+        let file = |x: &'static str| {
+            Ok(File::new_in_memory(Bytes::from(x), Some("photo.jpg".into()), None))
+        };
+        let files = || FileParts::from(vec![("a".into(), file("1")), ("b".into(), file("2"))]);
+
+        let dir = tempfile::tempdir().expect("Failed creating tmp dir");
+        let paths = files()
+            .persist_all_in_with(dir.path(), CollisionPolicy::AppendCounter)
+            .expect("Failed persisting files");
+        assert_eq!(paths[0].1, dir.path().join("photo.jpg"));
+        assert_eq!(paths[1].1, dir.path().join("photo-1.jpg"));
+        assert_eq!(std::fs::read(&paths[1].1).unwrap(), b"2");
+
+        let dir = tempfile::tempdir().expect("Failed creating tmp dir");
+        let res = files().persist_all_in_with(dir.path(), CollisionPolicy::ErrorIfExists);
+        assert!(
+            matches!(res, Err(Error::Io(ref e)) if e.kind() == std::io::ErrorKind::AlreadyExists)
+        );
+        assert_eq!(std::fs::read(dir.path().join("photo.jpg")).unwrap(), b"1");
+
+        let on_disk = || {
+            let tempfile = NamedTempFile::new().expect("Failed creating temp file");
+            Ok(File::new_with_file_name(tempfile, "photo.jpg".into()))
+        };
+        let res = FileParts::from(vec![("c".into(), on_disk())])
+            .persist_all_in_with(dir.path(), CollisionPolicy::ErrorIfExists);
+        assert!(
+            matches!(res, Err(Error::Io(ref e)) if e.kind() == std::io::ErrorKind::AlreadyExists)
+        );
+        let paths = FileParts::from(vec![("c".into(), on_disk())])
+            .persist_all_in_with(dir.path(), CollisionPolicy::AppendCounter)
+            .expect("Failed persisting files");
+        assert_eq!(paths[0].1, dir.path().join("photo-1.jpg"));
+
+        let dir = tempfile::tempdir().expect("Failed creating tmp dir");
+        let paths = files().persist_all_in(dir.path()).expect("Failed persisting files");
+        assert_eq!(paths[0].1, paths[1].1);
+        assert_eq!(std::fs::read(&paths[1].1).unwrap(), b"2");
+
+        assert_eq!(name_with_suffix("a.tar.gz", "1"), "a.tar-1.gz");
+        assert_eq!(name_with_suffix(".env", "1"), ".env-1");
+    }
+
+    #[cfg(feature = "tokio")]
+    #[actix_rt::test]
+    pub async fn persisting_async() {
+        let dir = tempfile::tempdir().expect("Failed creating tmp dir");
+
+        let file = File::new_in_memory(Bytes::from("Hello"), Some("a.txt".into()), None);
+        let path = file.persist_in_async(dir.path()).await.expect("Failed persisting file");
+        assert_eq!(path, dir.path().join("a.txt"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"Hello");
+
+        let mut tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        tempfile.write_all(b"World").unwrap();
+        let path = dir.path().join("b.txt");
+        File::new(tempfile, None, None)
+            .persist_at_async(&path)
+            .await
+            .expect("Failed persisting file");
+        assert_eq!(std::fs::read(&path).unwrap(), b"World");
+
+        let file = File::new_in_memory(Bytes::from("Hello"), None, None);
+        match file.into_async_file() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+            x => panic!("Unexpected result: {:?}", x.map(|_| ())),
+        }
+    }
+
+    #[test]
+    pub fn file_part_headers_are_kept() {
+        let headers = crate::part_headers(
+            vec![("Content-Type", &b"text/plain"[..]), ("X-Idempotency-Key", &b"abc"[..])]
+                .into_iter(),
+        );
+        let file = File::new_in_memory(Bytes::from("1"), None, None).with_headers(headers);
+
+        assert_eq!(file.headers().len(), 2);
+        assert_eq!(file.headers()[1].0, "x-idempotency-key");
+        assert_eq!(file.header("X-IDEMPOTENCY-KEY"), Some("abc"));
+        assert_eq!(file.header("x-other"), None);
+    }
+
+    #[test]
+    pub fn files_are_read_to_strings() {
+        let mut tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        tempfile.write_all(b"a,b\n1,2\n").expect("Failed writing to file.");
+        let file = File::new_with_file_name(tempfile, "data.csv".into());
+        assert_eq!(file.to_utf8_string().unwrap(), "a,b\n1,2\n");
+
+        let file = File::new_in_memory(Bytes::from(&b"x\xffy"[..]), None, None);
+        match file.to_utf8_string() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+        assert_eq!(file.to_string_lossy().unwrap(), "x\u{fffd}y");
+    }
+
+    #[test]
+    pub fn files_can_be_read_and_sought() {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        tempfile.write_all(b"Hello").expect("Failed writing to file.");
+        let disk = File::new_collected(tempfile, None, &mime::TEXT_PLAIN, 5, &Default::default());
+        let memory = File::new_in_memory(Bytes::from("Hello"), None, None);
+
+        for mut file in [disk, memory] {
+            let mut s = String::new();
+            file.read_to_string(&mut s).unwrap();
+            assert_eq!(s, "Hello");
+
+            assert_eq!(file.seek(SeekFrom::End(-2)).unwrap(), 3);
+            let mut s = String::new();
+            file.read_to_string(&mut s).unwrap();
+            assert_eq!(s, "lo");
+            assert!(file.seek(SeekFrom::Current(-10)).is_err());
+        }
+    }
+
+    #[cfg(feature = "infer")]
+    #[test]
+    pub fn content_types_are_sniffed() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+        let file = File::new_in_memory(Bytes::from(&png[..]), None, Some(&mime::TEXT_PLAIN));
+        assert_eq!(file.detected_content_type(), Some(&mime::IMAGE_PNG));
+
+        let mut tracker = crate::FileTracker::default();
+        tracker.push(&png[..4]);
+        tracker.push(&png[4..]);
+        let tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        let file = File::new_collected(tempfile, None, &mime::TEXT_PLAIN, 16, &tracker);
+        assert_eq!(file.detected_content_type(), Some(&mime::IMAGE_PNG));
+
+        let file = File::new_in_memory(Bytes::from("Hello"), None, None);
+        assert_eq!(file.detected_content_type(), None);
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    pub fn sha256_is_computed_while_collecting() {
+        let hello = "185f8db32271fe25f561a6fc938b2e264306ec304eda518007d1764826381969";
+
+        let file = File::new_in_memory(Bytes::from("Hello"), None, None);
+        assert_eq!(file.sha256_hex().as_deref(), Some(hello));
+
+        let mut tracker = crate::FileTracker::default();
+        tracker.push(b"Hel");
+        tracker.push(b"lo");
+        let tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        let file = File::new_collected(tempfile, None, &mime::TEXT_PLAIN, 5, &tracker);
+        assert_eq!(file.sha256_hex().as_deref(), Some(hello));
+
+        let tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        assert_eq!(File::new(tempfile, None, None).sha256_hex(), None);
+    }
+
+    #[test]
+    pub fn sort_by_name_is_stable() {
+        let mut texts = TextParts::from(vec![
+            ("b".into(), "1".into()),
+            ("a".into(), "2".into()),
+            ("b".into(), "3".into()),
+        ]);
+
+        texts.sort_by_name();
+
+        assert_eq!(texts.as_pairs(), vec![("a", "2"), ("b", "1"), ("b", "3")]);
+        assert_eq!(texts.grouped_by_name()["b"], vec![&Bytes::from("1"), &Bytes::from("3")]);
+    }
+
+    #[test]
+    pub fn text_parts_keep_declared_content_type() {
+        let mut parts = vec![
+            ("a".to_string(), Part::Text(Bytes::from("1"), None)),
+            ("b".to_string(), Part::Text(Bytes::from("{}"), Some(mime::APPLICATION_JSON))),
+        ]
+        .into_iter()
+        .collect::<Parts>();
+
+        assert_eq!(parts.texts.content_type("a"), None);
+        assert_eq!(parts.texts.content_type("b"), Some(&mime::APPLICATION_JSON));
+
+        parts.texts.take("a");
+        assert_eq!(parts.texts.content_type("b"), Some(&mime::APPLICATION_JSON));
+    }
+
+    #[test]
+    pub fn sensitive_fields_are_redacted_in_debug_output() {
+        let cfg = PartsConfig::default().with_sensitive_fields(vec!["password".into()]);
+        let mut texts = TextParts::from(vec![
+            ("user".into(), "ann".into()),
+            ("password".into(), "hunter2".into()),
+        ]);
+        texts.mark_sensitive(&cfg);
+
+        let debug = format!("{:?}", texts);
+        assert!(debug.contains("ann"));
+        assert!(debug.contains("«redacted»"));
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[test]
+    pub fn parts_remember_their_position() {
+        let mut parts = vec![
+            ("b".to_string(), Part::Text(Bytes::from("1"), None)),
+            ("f".to_string(), Part::File(Err(Error::MissingBoundary))),
+            ("x".to_string(), Part::Failed(Error::MissingBoundary)),
+            ("a".to_string(), Part::Text(Bytes::from("2"), None)),
+            ("c".to_string(), Part::Text(Bytes::from("3"), None)),
+        ]
+        .into_iter()
+        .collect::<Parts>();
+
+        let texts = parts.texts.iter_indexed().map(|(i, k, _)| (i, k)).collect::<Vec<_>>();
+        assert_eq!(texts, vec![(0, "b"), (3, "a"), (4, "c")]);
+        let files = parts.files.iter_indexed().map(|(i, k, _)| (i, k)).collect::<Vec<_>>();
+        assert_eq!(files, vec![(1, "f")]);
+
+        parts.texts.sort_by_name();
+        parts.texts.take("b");
+        let texts = parts.texts.iter_indexed().map(|(i, k, _)| (i, k)).collect::<Vec<_>>();
+        assert_eq!(texts, vec![(3, "a"), (4, "c")]);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    pub fn regex_patterns_classify_fields() {
+        let cfg = PartsConfig::default()
+            .with_file_field_pattern(regex::Regex::new(r"^doc_\d+_revision_\d+$").unwrap())
+            .with_text_field_pattern(regex::Regex::new(r"^note_").unwrap());
+
+        assert_eq!(classify_field(&cfg, "doc_3_revision_2", None, None), FieldKind::File);
+        assert_eq!(classify_field(&cfg, "doc_3", None, None), FieldKind::Text);
+        assert_eq!(classify_field(&cfg, "note_1", Some("note.txt"), None), FieldKind::Text);
+        assert_eq!(classify_field(&cfg, "upload", Some("a.txt"), None), FieldKind::File);
+    }
+
+    #[test]
+    pub fn content_types_classify_fields() {
+        let png = "image/png".parse::<mime::Mime>().unwrap();
+
+        let cfg = PartsConfig::default();
+        assert_eq!(classify_field(&cfg, "blob", None, Some(&png)), FieldKind::Text);
+
+        let cfg = PartsConfig::default().with_content_type_classification();
+        assert_eq!(classify_field(&cfg, "blob", None, Some(&png)), FieldKind::File);
+        assert_eq!(classify_field(&cfg, "title", None, Some(&mime::TEXT_PLAIN)), FieldKind::Text);
+        assert_eq!(classify_field(&cfg, "title", None, None), FieldKind::Text);
+
+        let cfg = PartsConfig::default().with_file_content_types(vec![mime::IMAGE_STAR]);
+        assert_eq!(classify_field(&cfg, "blob", None, Some(&png)), FieldKind::File);
+        assert_eq!(
+            classify_field(&cfg, "data", None, Some(&mime::APPLICATION_JSON)),
+            FieldKind::Text
+        );
+    }
+
+    #[test]
+    pub fn windows_paths_are_made_verbatim() {
+        let long_name = "a".repeat(300);
+
+        assert_eq!(
+            verbatim_path(&format!(r"C:\uploads\{}", long_name)),
+            Some(format!(r"\\?\C:\uploads\{}", long_name))
+        );
+        assert_eq!(
+            verbatim_path(r"\\server\share\uploads\file.txt").as_deref(),
+            Some(r"\\?\UNC\server\share\uploads\file.txt")
+        );
+        assert_eq!(
+            verbatim_path("C:/uploads/file.txt").as_deref(),
+            Some(r"\\?\C:\uploads\file.txt")
+        );
+        assert_eq!(verbatim_path(r"\\?\C:\uploads\file.txt"), None);
+        assert_eq!(verbatim_path(r"C:\uploads\..\file.txt"), None);
+        assert_eq!(verbatim_path(r"uploads\file.txt"), None);
+    }
+
+    #[test]
+    pub fn glob_patterns_match_field_names() {
+        assert!(glob_match("attachments[*]", "attachments[0]"));
+        assert!(glob_match("meta_*", "meta_"));
+        assert!(glob_match("doc_?_*", "doc_3_revision_2"));
+        assert!(glob_match("file", "file"));
+        assert!(!glob_match("file", "files"));
+        assert!(!glob_match("meta_*", "data_meta"));
+    }
+
+    #[test]
+    pub fn empty_file_inputs_are_skipped() {
+        let cfg = PartsConfig::default();
+        assert!(!cfg.skips_empty_file(0, Some("")));
+
+        let cfg = cfg.with_skip_empty_files(true);
+        assert!(cfg.skips_empty_file(0, Some("")));
+        assert!(cfg.skips_empty_file(0, None));
+        assert!(!cfg.skips_empty_file(0, Some("a.txt")));
+        assert!(!cfg.skips_empty_file(4, Some("")));
+
+        let mut parts = Vec::new();
+        push_part(&cfg, &mut parts, "upload".into(), Part::Skipped).unwrap();
+        assert!(parts.is_empty());
+    }
+
+    #[test]
+    pub fn text_is_trimmed_when_configured() {
+        let cfg = PartsConfig::default();
+        assert_eq!(
+            cfg.text_bytes(b" Hello\r\n".to_vec(), &mime::TEXT_PLAIN),
+            Bytes::from(" Hello\r\n")
+        );
+
+        let cfg = cfg.with_trim_text(true);
+        assert_eq!(cfg.text_bytes(b" Hello\r\n".to_vec(), &mime::TEXT_PLAIN), Bytes::from("Hello"));
+        assert_eq!(cfg.text_bytes(b"a b".to_vec(), &mime::TEXT_PLAIN), Bytes::from("a b"));
+        assert_eq!(cfg.text_bytes(b" \r\n".to_vec(), &mime::TEXT_PLAIN), Bytes::from(""));
+    }
+
+    #[test]
+    pub fn bom_and_newlines_are_normalized_when_configured() {
+        let cfg = PartsConfig::default();
+        assert_eq!(
+            cfg.text_bytes(b"\xef\xbb\xbfa\r\nb".to_vec(), &mime::TEXT_PLAIN),
+            Bytes::from("\u{feff}a\r\nb")
+        );
+
+        let cfg = cfg.with_strip_bom(true).with_normalize_newlines(true);
+        assert_eq!(
+            cfg.text_bytes(b"\xef\xbb\xbfa\r\nb".to_vec(), &mime::TEXT_PLAIN),
+            Bytes::from("a\nb")
+        );
+        assert_eq!(
+            cfg.text_bytes(b"a\rb\r\r\n".to_vec(), &mime::TEXT_PLAIN),
+            Bytes::from("a\rb\r\n")
+        );
+    }
+
+    #[test]
+    pub fn invalid_utf8_fails_in_strict_mode() {
+        let cfg = PartsConfig::default();
+        assert!(matches!(
+            cfg.text_part("a", vec![0xff], &mime::TEXT_PLAIN, false, None),
+            Ok(Part::Text(..))
+        ));
+
+        let cfg = cfg.with_strict_utf8(true);
+        assert!(matches!(
+            cfg.text_part("a", b"ok".to_vec(), &mime::TEXT_PLAIN, false, None),
+            Ok(Part::Text(..))
+        ));
+        match cfg.text_part("a", vec![0xff], &mime::TEXT_PLAIN, false, None) {
+            Err(Error::InvalidUtf8 { field }) => assert_eq!(field, "a"),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+
+        let cfg = cfg.with_error_mode(ErrorMode::Collect);
+        assert!(matches!(
+            cfg.text_part("a", vec![0xff], &mime::TEXT_PLAIN, false, None),
+            Ok(Part::Failed(Error::InvalidUtf8 { .. }))
+        ));
+    }
+
+    #[cfg(feature = "base64")]
+    #[test]
+    pub fn base64_text_is_decoded() {
+        let cfg = PartsConfig::default();
+        match cfg.text_part("a", b"aGVsbG8=".to_vec(), &mime::TEXT_PLAIN, true, None) {
+            Ok(Part::Text(x, _)) => assert_eq!(x, Bytes::from("aGVsbG8=")),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+
+        let cfg = cfg.with_base64_text_decoding(true);
+        match cfg.text_part("a", b"aGVs\r\nbG8=".to_vec(), &mime::TEXT_PLAIN, true, None) {
+            Ok(Part::Text(x, _)) => assert_eq!(x, Bytes::from("hello")),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+        match cfg.text_part("a", b"aGVsbG8=".to_vec(), &mime::TEXT_PLAIN, false, None) {
+            Ok(Part::Text(x, _)) => assert_eq!(x, Bytes::from("aGVsbG8=")),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+        assert!(matches!(
+            cfg.text_part("a", b"not base64!".to_vec(), &mime::TEXT_PLAIN, true, None),
+            Err(Error::InvalidValue { .. })
+        ));
+
+        assert!(crate::is_base64_encoded(Some(&b" Base64 "[..])));
+        assert!(!crate::is_base64_encoded(Some(&b"8bit"[..])));
+        assert!(!crate::is_base64_encoded(None));
+    }
+
+    #[test]
+    pub fn field_names_are_matched_case_insensitively() {
+        let cfg = PartsConfig::default().with_file_fields(vec!["Upload".into()]);
+        assert_eq!(cfg.field_name("File".into()), "File");
+        assert_eq!(classify_field(&cfg, "upload", None, None), FieldKind::Text);
+
+        let cfg = cfg.with_case_insensitive_fields(true);
+        assert_eq!(cfg.field_name("File".into()), "file");
+        assert_eq!(classify_field(&cfg, "upload", None, None), FieldKind::File);
+        assert_eq!(classify_field(&cfg, "UPLOAD", None, None), FieldKind::File);
+    }
+
+    #[test]
+    pub fn array_field_suffixes_are_normalized() {
+        let cfg = PartsConfig::default();
+        assert_eq!(cfg.field_name("photos[]".into()), "photos[]");
+
+        let cfg = cfg.with_normalize_array_fields(true);
+        assert_eq!(cfg.field_name("photos[]".into()), "photos");
+        assert_eq!(cfg.field_name("photos[12]".into()), "photos");
+        assert_eq!(cfg.field_name("user[name]".into()), "user[name]");
+        assert_eq!(cfg.field_name("photos".into()), "photos");
+    }
+
+    #[test]
+    pub fn field_filter_decides_field_actions() {
+        let cfg = PartsConfig::default();
+        assert_eq!(cfg.field_action("a", None, &mime::TEXT_PLAIN), FieldAction::Accept);
+
+        let cfg = cfg.with_field_filter(|name, file_name, mime| match (name, file_name) {
+            ("secret", _) => FieldAction::Discard,
+            (_, Some(_)) if *mime == mime::TEXT_PLAIN => FieldAction::ForceText,
+            _ => FieldAction::Accept,
+        });
+        assert_eq!(cfg.field_action("secret", None, &mime::TEXT_PLAIN), FieldAction::Discard);
+        assert_eq!(cfg.field_action("a", Some("a.txt"), &mime::TEXT_PLAIN), FieldAction::ForceText);
+        assert_eq!(cfg.field_action("a", Some("a.png"), &mime::IMAGE_PNG), FieldAction::Accept);
+    }
+
+    #[test]
+    pub fn unlisted_fields_are_discarded() {
+        let cfg = PartsConfig::default().with_only_fields(vec!["file".into(), "meta_*".into()]);
+        assert_eq!(cfg.field_action("file", Some("a.png"), &mime::IMAGE_PNG), FieldAction::Accept);
+        assert_eq!(cfg.field_action("meta_a", None, &mime::TEXT_PLAIN), FieldAction::Accept);
+        assert_eq!(cfg.field_action("other", None, &mime::TEXT_PLAIN), FieldAction::Discard);
+    }
+
+    #[cfg(feature = "encoding_rs")]
+    #[test]
+    pub fn text_is_decoded_from_declared_charset() {
+        let mime = "text/plain; charset=windows-1252".parse::<mime::Mime>().unwrap();
+
+        let cfg = PartsConfig::default();
+        assert_eq!(cfg.text_bytes(b"caf\xe9".to_vec(), &mime), Bytes::from(&b"caf\xe9"[..]));
+
+        let cfg = cfg.with_text_charset_decoding(true);
+        assert_eq!(cfg.text_bytes(b"caf\xe9".to_vec(), &mime), Bytes::from("café"));
+        assert_eq!(cfg.text_bytes("café".into(), &mime::TEXT_PLAIN), Bytes::from("café"));
+    }
+
+    #[test]
+    pub fn sizes_are_parsed_with_units() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("10MB").unwrap(), 10_000_000);
+        assert_eq!(parse_size("512 KiB").unwrap(), 512 * 1024);
+        assert_eq!(parse_size("1gib").unwrap(), 1 << 30);
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("MB").is_err());
+
+        let cfg = PartsConfig::default().with_file_limit_str("2KB").unwrap();
+        assert_eq!(cfg.file_limit, Some(2000));
+    }
+
+    #[test]
+    pub fn config_is_validated() {
+        assert!(PartsConfig::default().validate().is_ok());
+
+        let cfg = PartsConfig::recommended().with_file_limit(1024);
+        assert!(cfg.validate().is_ok());
+
+        let cfg = PartsConfig::unlimited().with_file_limit(1024).with_total_limit(512);
+        assert!(matches!(cfg.validate(), Err(Error::InvalidConfig { .. })));
+
+        let dir = tempfile::tempdir().expect("Failed creating tmp dir");
+        let cfg = PartsConfig::default().with_temp_dir(dir.path());
+        assert!(cfg.validate().is_ok());
+
+        let cfg = PartsConfig::default().with_temp_dir(dir.path().join("missing"));
+        assert!(matches!(cfg.validate(), Err(Error::InvalidConfig { .. })));
+
+        // Probed once, then taken as known good
+        let checked = tempfile::tempdir().expect("Failed creating tmp dir");
+        let cfg = PartsConfig::default().with_temp_dir(checked.path());
+        assert!(cfg.validate().is_ok());
+        drop(checked);
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    pub fn missing_temp_dir_is_created() {
+        let dir = tempfile::tempdir().expect("Failed creating tmp dir");
+        let nested = dir.path().join("a").join("b");
+
+        let cfg = PartsConfig::default().with_temp_dir(&nested);
+        assert!(create_temp_file(&cfg, 0).is_err());
+
+        let cfg = cfg.with_create_temp_dir(true);
+        let file = create_temp_file(&cfg, 0).expect("Failed creating tempfile");
+        assert_eq!(file.path().parent(), Some(nested.as_path()));
+    }
+
+    #[cfg(not(feature = "v1"))]
+    #[test]
+    pub fn chunk_timeout_is_the_sooner_limit() {
+        use std::time::Duration;
+
+        let mut req_state = RequestState::default();
+        assert!(req_state.chunk_timeout(&PartsConfig::default(), Some("a")).is_none());
+
+        let cfg = PartsConfig::default()
+            .with_field_timeout(Duration::from_secs(30))
+            .with_min_transfer_rate(100, Duration::from_secs(10));
+        req_state.add_part(&cfg).unwrap();
+
+        match req_state.chunk_timeout(&cfg, Some("a")) {
+            Some((timeout, Error::TransferTooSlow { rate: 100 })) => {
+                assert!(timeout <= Duration::from_secs(10))
+            }
+            x => panic!("Unexpected result: {:?}", x),
+        }
+
+        req_state.add_bytes(&cfg, 100 * 60).unwrap();
+        match req_state.chunk_timeout(&cfg, Some("a")) {
+            Some((timeout, Error::FieldTimeout { .. })) => {
+                assert_eq!(timeout, Duration::from_secs(30))
+            }
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[cfg(not(feature = "v1"))]
+    #[test]
+    pub fn reads_are_paced_to_max_transfer_rate() {
+        use std::time::Duration;
+
+        let cfg = PartsConfig::default().with_max_transfer_rate(1000);
+        let mut req_state = RequestState::default();
+        req_state.add_part(&cfg).unwrap();
+        assert!(req_state.pacing_delay(&cfg).is_none());
+
+        req_state.add_bytes(&cfg, 5000).unwrap();
+        let delay = req_state.pacing_delay(&cfg).expect("Reads were not paced");
+        assert!(delay > Duration::from_secs(4) && delay <= Duration::from_secs(5));
+    }
+
+    #[test]
+    pub fn part_header_size_is_limited() {
+        assert!(PartsConfig::default().check_part_header_size(100_000).is_ok());
+
+        let cfg = PartsConfig::default().with_max_part_header_size(1024);
+        assert!(cfg.check_part_header_size(1024).is_ok());
+        match cfg.check_part_header_size(1025) {
+            Err(Error::HeadersTooLarge { limit: 1024 }) => {}
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    pub fn texts_are_limited_across_fields() {
+        let cfg = PartsConfig::default().with_total_text_limit(100);
+        let mut req_state = RequestState::default();
+
+        for _ in 0..10 {
+            req_state.add_text(&cfg, 10).expect("Texts were limited too soon");
+        }
+
+        match req_state.add_text(&cfg, 1) {
+            Err(Error::TextsTooLarge { limit: 100 }) => {}
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    pub fn content_length_is_checked_against_total_limit() {
+        let cfg = PartsConfig::default();
+        assert!(cfg.check_content_length(Some(usize::MAX)).is_ok());
+
+        let cfg = cfg.with_total_limit(1000);
+        assert!(cfg.check_content_length(None).is_ok());
+        assert!(cfg.check_content_length(Some(1000)).is_ok());
+        match cfg.check_content_length(Some(1001)) {
+            Err(Error::RequestTooLarge { limit: 1000 }) => {}
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[cfg(feature = "fs2")]
+    #[test]
+    pub fn free_space_is_checked_for_tempfiles() {
+        assert!(create_temp_file(&PartsConfig::default(), 0).is_ok());
+
+        let cfg = PartsConfig::default().with_min_free_space(usize::MAX);
+        let e = create_temp_file(&cfg, 0).expect_err("Free space was not checked");
+        match e.get_ref().and_then(|x| x.downcast_ref::<Error>()) {
+            Some(Error::InsufficientStorage { .. }) => {}
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    pub fn tempfiles_are_limited_per_request() {
+        let cfg = PartsConfig::default().with_temp_quota(1000);
+        let mut req_state = RequestState::default();
+
+        req_state.add_temp_bytes(&cfg, 600).expect("Quota was exceeded too soon");
+        match req_state.add_temp_bytes(&cfg, 600) {
+            Err(Error::TempQuotaExceeded { limit: 1000 }) => {}
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    pub fn parts_are_capped() {
+        let cfg = PartsConfig::unlimited().with_max_parts(2);
+        let mut req_state = RequestState::default();
+
+        req_state.add_part(&cfg).unwrap();
+        assert_eq!(req_state.part_index(), 0);
+        req_state.add_part(&cfg).unwrap();
+        assert_eq!(req_state.part_index(), 1);
+        match req_state.add_part(&cfg) {
+            Err(Error::TooManyParts { limit: 2 }) => {}
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[cfg(feature = "v4")]
+    #[actix_rt::test]
+    pub async fn dropped_parts_count_toward_max_parts_only() {
+        use crate::actix_web::FromRequest;
+
+        async fn extract(cfg: PartsConfig) -> Result<Parts, crate::actix_web::Error> {
+            let body = "--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+                        --b\r\nContent-Disposition: form-data; name=\"skip\"\r\n\r\n2\r\n\
+                        --b\r\nContent-Disposition: form-data; name=\"c\"\r\n\r\n3\r\n\
+                        --b--\r\n";
+            let (req, mut payload) = crate::actix_web::test::TestRequest::post()
+                .insert_header(("content-type", "multipart/form-data; boundary=b"))
+                .app_data(cfg)
+                .set_payload(body)
+                .to_http_parts();
+            Parts::from_request(&req, &mut payload).await
+        }
+
+        let cfg = PartsConfig::unlimited().with_only_fields(vec!["a".into(), "c".into()]);
+
+        let parts = extract(cfg.clone().with_max_fields(2)).await.expect("Failed reading parts");
+        assert_eq!(parts.texts.as_pairs(), vec![("a", "1"), ("c", "3")]);
+
+        let e = extract(cfg.with_max_parts(2)).await.expect_err("Read too many parts");
+        assert!(matches!(e.as_error::<Error>(), Some(Error::TooManyParts { limit: 2 })));
+    }
+
+    #[cfg(feature = "v4")]
+    #[actix_rt::test]
+    pub async fn routed_fields_are_handled_in_place() {
+        use crate::actix_web::FromRequest;
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        async fn extract(cfg: PartsConfig) -> Result<Parts, crate::actix_web::Error> {
+            let body = "--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+                        --b\r\nContent-Disposition: form-data; name=\"upload\"; \
+                        filename=\"a.txt\"\r\n\r\nHello\r\n\
+                        --b\r\nContent-Disposition: form-data; name=\"c\"\r\n\r\n3\r\n\
+                        --b--\r\n";
+            let (req, mut payload) = crate::actix_web::test::TestRequest::post()
+                .insert_header(("content-type", "multipart/form-data; boundary=b"))
+                .app_data(cfg)
+                .set_payload(body)
+                .to_http_parts();
+            Parts::from_request(&req, &mut payload).await
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = calls.clone();
+        let cfg = PartsConfig::unlimited().with_field_router("upload", move |mut parts: Parts| {
+            let handler_calls = handler_calls.clone();
+            async move {
+                handler_calls.fetch_add(1, Ordering::SeqCst);
+                let file = parts.files.take("upload").pop().expect("Missing routed file");
+                let text = file.to_utf8_string().expect("Failed reading routed file");
+                Ok(std::iter::once(("moved".to_string(), Part::Text(text.into(), None))).collect())
+            }
+        });
+
+        let mut parts = extract(cfg).await.expect("Failed reading parts");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(parts.texts.as_pairs(), vec![("a", "1"), ("moved", "Hello"), ("c", "3")]);
+        assert!(parts.files.take("upload").is_empty());
+
+        let cfg = PartsConfig::unlimited().with_field_router("upload", |_| async {
+            Err(crate::actix_web::error::ErrorForbidden("No uploads"))
+        });
+
+        let e = extract(cfg).await.expect_err("Ignored the handler error");
+        assert_eq!(
+            e.as_response_error().status_code(),
+            crate::actix_web::http::StatusCode::FORBIDDEN
+        );
+    }
+
+    #[cfg(feature = "v4")]
+    #[actix_rt::test]
+    pub async fn spilled_texts_follow_file_policies() {
+        use crate::{actix_web::FromRequest, DisallowedFilePolicy};
+
+        async fn extract(cfg: PartsConfig) -> Result<Parts, crate::actix_web::Error> {
+            let body = "--b\r\nContent-Disposition: form-data; name=\"notes\"\r\n\r\n\
+                        Hello, world\r\n--b--\r\n";
+            let (req, mut payload) = crate::actix_web::test::TestRequest::post()
+                .insert_header(("content-type", "multipart/form-data; boundary=b"))
+                .app_data(cfg)
+                .set_payload(body)
+                .to_http_parts();
+            Parts::from_request(&req, &mut payload).await
+        }
+
+        let cfg = PartsConfig::unlimited().with_text_limit(5);
+
+        let mut parts = extract(cfg.clone()).await.expect("Failed reading parts");
+        let file = parts.files.take("notes").pop().expect("Text was not spilled");
+        assert_eq!(file.to_utf8_string().unwrap(), "Hello, world");
+
+        let pngs_only = cfg.clone().with_allowed_mime_types(vec![mime::IMAGE_PNG]);
+
+        let parts = extract(pngs_only.clone()).await.expect("Failed reading parts");
+        assert!(matches!(parts.files.errors()[..], [("notes", Error::DisallowedMimeType { .. })]));
+
+        let e = extract(pngs_only.with_disallowed_file_policy(DisallowedFilePolicy::Reject))
+            .await
+            .expect_err("Spilled a disallowed file");
+        assert!(matches!(e.as_error::<Error>(), Some(Error::DisallowedMimeType { .. })));
+
+        let parts = extract(cfg.with_discard_files(true)).await.expect("Failed reading parts");
+        assert_eq!(parts.files.len_with_errors(), 0);
+        assert_eq!(parts.discarded_files.len(), 1);
+        assert_eq!(parts.discarded_files[0].1.size, 12);
+    }
+
+    #[cfg(feature = "v4")]
+    #[actix_rt::test]
+    pub async fn stalls_between_fields_time_out() {
+        use crate::actix_web::{dev, error::PayloadError, rt, web::Bytes, FromRequest};
+        use futures_v03::stream::{self, StreamExt};
+        use std::time::Duration;
+
+        let body = "--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--b\r\n";
+        let chunks = stream::iter(vec![Ok::<_, PayloadError>(Bytes::from_static(body.as_bytes()))]);
+        let mut payload =
+            dev::Payload::Stream { payload: chunks.chain(stream::pending()).boxed_local() };
+
+        let cfg = PartsConfig::unlimited().with_field_timeout(Duration::from_millis(50));
+        let (req, _) = crate::actix_web::test::TestRequest::post()
+            .insert_header(("content-type", "multipart/form-data; boundary=b"))
+            .app_data(cfg)
+            .to_http_parts();
+
+        let e = rt::time::timeout(Duration::from_secs(5), Parts::from_request(&req, &mut payload))
+            .await
+            .expect("Stalled between fields")
+            .expect_err("Read a stalled request");
+        assert!(matches!(e.as_error::<Error>(), Some(Error::FieldTimeout { name: None })));
+    }
+
+    #[cfg(feature = "v4")]
+    #[actix_rt::test]
+    pub async fn stalled_requests_miss_the_deadline() {
+        use crate::actix_web::{dev, error::PayloadError, rt, web::Bytes, FromRequest};
+        use futures_v03::stream::{self, StreamExt};
+        use std::time::Duration;
+
+        let body = "--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n";
+        let chunks = stream::iter(vec![Ok::<_, PayloadError>(Bytes::from_static(body.as_bytes()))]);
+        let mut payload =
+            dev::Payload::Stream { payload: chunks.chain(stream::pending()).boxed_local() };
+
+        let cfg = PartsConfig::unlimited().with_deadline(Duration::from_millis(50));
+        let (req, _) = crate::actix_web::test::TestRequest::post()
+            .insert_header(("content-type", "multipart/form-data; boundary=b"))
+            .app_data(cfg)
+            .to_http_parts();
+
+        let e = rt::time::timeout(Duration::from_secs(5), Parts::from_request(&req, &mut payload))
+            .await
+            .expect("Ignored the deadline")
+            .expect_err("Read a stalled request");
+        assert!(matches!(e.as_error::<Error>(), Some(Error::DeadlineExceeded)));
+        assert_eq!(e.as_response_error().status_code().as_u16(), 408);
+    }
+
+    #[cfg(feature = "v4")]
+    #[actix_rt::test]
+    pub async fn stalled_fields_time_out() {
+        use crate::actix_web::{dev, error::PayloadError, rt, web::Bytes, FromRequest};
+        use futures_v03::stream::{self, StreamExt};
+        use std::time::Duration;
+
+        let body = "--b\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+                    \r\nHello";
+        let chunks = stream::iter(vec![Ok::<_, PayloadError>(Bytes::from_static(body.as_bytes()))]);
+        let mut payload =
+            dev::Payload::Stream { payload: chunks.chain(stream::pending()).boxed_local() };
+
+        let cfg = PartsConfig::unlimited()
+            .with_field_timeout(Duration::from_millis(50))
+            .with_deadline(Duration::from_secs(5));
+        let (req, _) = crate::actix_web::test::TestRequest::post()
+            .insert_header(("content-type", "multipart/form-data; boundary=b"))
+            .app_data(cfg)
+            .to_http_parts();
+
+        let e = rt::time::timeout(Duration::from_secs(5), Parts::from_request(&req, &mut payload))
+            .await
+            .expect("Ignored the field timeout")
+            .expect_err("Read a stalled field");
+        match e.as_error::<Error>() {
+            Some(Error::FieldTimeout { name: Some(name) }) => assert_eq!(name, "upload"),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[cfg(feature = "v4")]
+    #[actix_rt::test]
+    pub async fn oversize_requests_are_rejected() {
+        use crate::actix_web::{dev, error::PayloadError, rt, web::Bytes, FromRequest};
+        use futures_v03::stream::{self, StreamExt};
+        use std::time::Duration;
+
+        // The body is streamed without a `Content-Length` unless one is given, and never ends,
+        // so a request that is read rather than rejected up front runs into the timeout
+        async fn extract(
+            cfg: PartsConfig,
+            body: &'static str,
+            content_length: Option<&str>,
+        ) -> Result<Parts, crate::actix_web::Error> {
+            let chunks =
+                stream::iter(vec![Ok::<_, PayloadError>(Bytes::from_static(body.as_bytes()))]);
+            let mut payload =
+                dev::Payload::Stream { payload: chunks.chain(stream::pending()).boxed_local() };
+
+            let mut req = crate::actix_web::test::TestRequest::post()
+                .insert_header(("content-type", "multipart/form-data; boundary=b"))
+                .app_data(cfg.with_field_timeout(Duration::from_millis(200)));
+            if let Some(len) = content_length {
+                req = req.insert_header(("content-length", len));
+            }
+            let (req, _) = req.to_http_parts();
+
+            rt::time::timeout(Duration::from_secs(5), Parts::from_request(&req, &mut payload))
+                .await
+                .expect("Request stalled")
+        }
+
+        let cfg = PartsConfig::unlimited().with_total_limit(10);
+        let e = extract(cfg.clone(), "", Some("1000000")).await.expect_err("Read a large body");
+        assert!(matches!(e.as_error::<Error>(), Some(Error::RequestTooLarge { limit: 10 })));
+        assert_eq!(e.as_response_error().status_code().as_u16(), 413);
+
+        let body = "--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n\
+                    Hello, world\r\n--b--\r\n";
+        let e = extract(cfg, body, None).await.expect_err("Read past the total limit");
+        assert!(matches!(e.as_error::<Error>(), Some(Error::RequestTooLarge { limit: 10 })));
+
+        let body = "--b\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+                    \r\nHello, world\r\n--b--\r\n";
+        let cfg = PartsConfig::unlimited().with_file_limit(5);
+
+        let e =
+            extract(cfg.clone().with_oversize_policy(OversizePolicy::RejectRequest), body, None)
+                .await
+                .expect_err("Kept a file past the file limit");
+        assert!(matches!(e.as_error::<Error>(), Some(Error::FileTooLarge { limit: 5, .. })));
+        assert_eq!(e.as_response_error().status_code().as_u16(), 413);
+
+        let parts = extract(cfg.with_oversize_policy(OversizePolicy::SkipPart), body, None)
+            .await
+            .expect("Failed reading parts");
+        assert!(matches!(parts.files.errors()[..], [("upload", Error::FileTooLarge { .. })]));
+    }
+
+    #[test]
+    pub fn errors_have_status_codes() {
+        use crate::actix_web::http::StatusCode;
+
+        let e =
+            Error::FileTooLarge { field: "big".into(), limit: 10, received: 11, file_name: None };
+        assert_eq!(e.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(Error::InvalidSignature.status_code(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            Error::MissingFields { fields: vec!["a".into()] }.status_code(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let e = Error::Io(std::io::Error::other("oops"));
+        assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let e = Error::FileTooLarge {
+            field: "attachment[2]".into(),
+            limit: 10,
+            received: 18,
+            file_name: Some("photo.jpg".into()),
+        };
+        assert_eq!(e.field(), Some("attachment[2]"));
+        assert_eq!(
+            e.to_string(),
+            "File is too large (18 bytes, limit: 10 bytes): attachment[2] (photo.jpg)"
+        );
+
+        let e = Error::MissingFieldName { index: 2 };
+        assert_eq!(e.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(e.to_string(), "Field name is required (part index: 2)");
+    }
+
+    #[test]
+    pub fn errors_go_through_the_error_handler() {
+        use crate::actix_web::http::StatusCode;
+
+        let req = crate::actix_web::test::TestRequest::default().to_http_request();
+        let status = |e: crate::actix_web::Error| e.as_response_error().error_response().status();
+
+        let cfg = PartsConfig::default();
+        assert_eq!(
+            status(cfg.error_response(Error::InvalidSignature, &req)),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let cfg = cfg.with_error_handler(|e, _| crate::actix_web::error::ErrorConflict(e));
+        assert_eq!(status(cfg.error_response(Error::InvalidSignature, &req)), StatusCode::CONFLICT);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn errors_are_serialized() {
+        let e = Error::TextTooLarge { limit: 10, name: "title".into() };
+        assert_eq!(
+            serde_json::to_value(&e).unwrap(),
+            serde_json::json!({
+                "kind": "text_too_large",
+                "message": "Text field is too large (limit: 10 bytes): title",
+                "status": 400,
+                "field": "title",
+            })
+        );
+
+        let e = serde_json::to_value(&Error::InvalidSignature).unwrap();
+        assert_eq!(e["field"], serde_json::Value::Null);
+    }
+
+    #[cfg(feature = "problem-json")]
+    #[test]
+    pub fn errors_are_problem_documents() {
+        let e = Error::DuplicateField { name: "title".into() };
+        assert_eq!(
+            e.to_problem_json(),
+            serde_json::json!({
+                "type": "urn:awmp:error:duplicate_field",
+                "title": "Bad Request",
+                "status": 400,
+                "detail": "Field was sent more than once: title",
+                "field": "title",
+            })
+        );
+
+        assert!(Error::InvalidSignature.to_problem_json().get("field").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn full_disks_are_told_apart() {
+        use crate::actix_web::http::StatusCode;
+
+        for code in [libc::ENOSPC, libc::EDQUOT].iter() {
+            let e = temp_file_error(std::io::Error::from_raw_os_error(*code));
+            assert!(matches!(e, Error::StorageExhausted { .. }));
+            assert_eq!(e.status_code(), StatusCode::INSUFFICIENT_STORAGE);
+        }
+
+        let e = temp_file_error(std::io::Error::from_raw_os_error(libc::EACCES));
+        assert!(matches!(e, Error::Io(_)));
+    }
+
+    #[test]
+    pub fn request_content_types_are_checked() {
+        assert!(check_request_content_type(Some(b"multipart/form-data; boundary=abc")).is_ok());
+
+        assert!(matches!(
+            check_request_content_type(None),
+            Err(Error::NotMultipart { content_type: None })
+        ));
+        assert!(matches!(
+            check_request_content_type(Some(b"application/json")),
+            Err(Error::NotMultipart { content_type: Some(_) })
+        ));
+        assert!(matches!(
+            check_request_content_type(Some(b"multipart/form-data")),
+            Err(Error::MissingBoundary)
+        ));
+        assert!(matches!(
+            check_request_content_type(Some(b"multipart/form-data; boundary=")),
+            Err(Error::MissingBoundary)
+        ));
+    }
+
+    #[test]
+    pub fn declared_content_types_are_parsed() {
+        let cfg = PartsConfig::default();
+        assert_eq!(declared_content_type(&cfg, "a", None).unwrap(), None);
+        assert_eq!(
+            declared_content_type(&cfg, "a", Some(b"text/plain")).unwrap(),
+            Some(mime::TEXT_PLAIN)
+        );
+        assert_eq!(declared_content_type(&cfg, "a", Some(b"not a type")).unwrap(), None);
+
+        let cfg = cfg.with_strict_content_types(true);
+        match declared_content_type(&cfg, "a", Some(b"not a type")) {
+            Err(Error::InvalidContentType { field, got }) => {
+                assert_eq!(field, "a");
+                assert_eq!(got, "not a type");
+            }
+            x => panic!("Unexpected result: {:?}", x),
+        }
     }
 }
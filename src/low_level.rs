@@ -0,0 +1,265 @@
+//! Building blocks for composing custom extractors
+//!
+//! Enabled with the `low-level` feature. These are the same pieces the bundled `FromRequest`
+//! implementations use, so a wrapper extractor (e.g., "`Parts` + auth claim") can read fields
+//! with `read_field` from the version module and collect the results into `Parts` without
+//! reimplementing the streaming loop.
+
+use super::*;
+
+#[cfg(not(feature = "v1"))]
+use std::time::Instant;
+
+/// The result of reading a single multipart field
+// `File` is kept unboxed, as it is in `FileParts`, which every file part ends up in
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum Part {
+    /// A text field and the `Content-Type` it was sent with, if any
+    ///
+    /// Its `Debug` output includes the value, even for `PartsConfig::with_sensitive_fields`.
+    Text(Bytes, Option<mime::Mime>),
+    File(Result<File, Error>),
+    Discarded(FileMetadata),
+    /// A field that failed under `ErrorMode::Collect`
+    Failed(Error),
+    /// A field dropped by the configuration, which is left out of `Parts`
+    Skipped,
+}
+
+/// How a field will be collected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Text,
+    File,
+}
+
+/// Running totals for a single request, shared by all of its fields
+#[derive(Debug, Default)]
+pub struct RequestState {
+    total_len: usize,
+    text_len: usize,
+    temp_len: usize,
+    file_count: usize,
+    field_count: usize,
+    part_count: usize,
+    file_types: Vec<mime::Mime>,
+    #[cfg(not(feature = "v1"))]
+    started: Option<Instant>,
+}
+
+impl RequestState {
+    /// Count bytes received for any field, checking the request-wide limits
+    pub(crate) fn add_bytes(&mut self, cfg: &PartsConfig, len: usize) -> Result<(), Error> {
+        self.total_len += len;
+
+        match cfg.total_byte_limit() {
+            Some(limit) if self.total_len > limit => Err(Error::RequestTooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count bytes collected in memory for text fields, checking the request-wide limit
+    pub(crate) fn add_text(&mut self, cfg: &PartsConfig, len: usize) -> Result<(), Error> {
+        self.text_len += len;
+
+        match cfg.total_text_limit {
+            Some(limit) if self.text_len > limit => Err(Error::TextsTooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count bytes written to tempfiles, checking the request-wide quota
+    pub(crate) fn add_temp_bytes(&mut self, cfg: &PartsConfig, len: usize) -> Result<(), Error> {
+        self.temp_len += len;
+
+        match cfg.temp_quota {
+            Some(limit) if self.temp_len > limit => Err(Error::TempQuotaExceeded { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a new part before anything else is done with it, checking the hard part limit
+    pub(crate) fn add_part(&mut self, cfg: &PartsConfig) -> Result<(), Error> {
+        self.part_count += 1;
+
+        #[cfg(not(feature = "v1"))]
+        {
+            self.started.get_or_insert_with(Instant::now);
+        }
+
+        match cfg.max_parts {
+            Some(limit) if self.part_count > limit => Err(Error::TooManyParts { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The zero-based index of the part being read
+    pub(crate) fn part_index(&self) -> usize {
+        self.part_count.saturating_sub(1)
+    }
+
+    /// Count a field of any kind that is not dropped by the configuration, checking the
+    /// request-wide limits
+    pub(crate) fn add_field(&mut self, cfg: &PartsConfig) -> Result<(), Error> {
+        self.field_count += 1;
+
+        match cfg.max_fields {
+            Some(limit) if self.field_count > limit => Err(Error::TooManyFields { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a field being saved as a file, checking the request-wide limits
+    pub(crate) fn add_file(&mut self, cfg: &PartsConfig) -> Result<(), Error> {
+        self.file_count += 1;
+
+        match cfg.max_files {
+            Some(limit) if self.file_count > limit => Err(Error::TooManyFiles { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// How long to wait for the next chunk of a field, or for the next field if `name` is
+    /// `None`, and the error to fail with after that
+    ///
+    /// This is the sooner of the field timeout and the time until the request falls below the
+    /// minimum transfer rate.
+    #[cfg(not(feature = "v1"))]
+    pub(crate) fn chunk_timeout(
+        &self,
+        cfg: &PartsConfig,
+        name: Option<&str>,
+    ) -> Option<(Duration, Error)> {
+        let field_timeout =
+            cfg.field_timeout.map(|x| (x, Error::FieldTimeout { name: name.map(String::from) }));
+
+        let rate_timeout =
+            cfg.min_transfer_rate.zip(self.started).map(|((rate, grace), started)| {
+                let covered = Duration::from_secs_f64(self.total_len as f64 / rate.max(1) as f64);
+                let timeout = grace.max(covered).saturating_sub(started.elapsed());
+                (timeout, Error::TransferTooSlow { rate })
+            });
+
+        match (field_timeout, rate_timeout) {
+            (Some(x), Some(y)) => Some(if x.0 <= y.0 { x } else { y }),
+            (x, y) => x.or(y),
+        }
+    }
+
+    /// How long to pause before reading more, to keep to the maximum transfer rate
+    #[cfg(not(feature = "v1"))]
+    pub(crate) fn pacing_delay(&self, cfg: &PartsConfig) -> Option<Duration> {
+        let (rate, started) = cfg.max_transfer_rate.zip(self.started)?;
+        let due = Duration::from_secs_f64(self.total_len as f64 / rate.max(1) as f64);
+        due.checked_sub(started.elapsed()).filter(|x| !x.is_zero())
+    }
+
+    /// Record the declared content type of a new file field, checking the configured file mix
+    pub(crate) fn add_file_type(
+        &mut self,
+        cfg: &PartsConfig,
+        mime_type: &mime::Mime,
+    ) -> Result<(), Error> {
+        let file_mix = match cfg.file_mix {
+            Some(ref x) => x,
+            None => return Ok(()),
+        };
+
+        self.file_types.push(mime_type.clone());
+
+        if file_mix.allows(&self.file_types) {
+            Ok(())
+        } else {
+            Err(Error::FileMixNotAllowed)
+        }
+    }
+}
+
+/// Decide whether a field should be collected as text or saved as a file
+///
+/// Fields with a filename are files unless matched by `text_fields` (or the text field
+/// pattern); fields without one are texts unless matched by `file_fields` (or the file field
+/// pattern) or sent with a content type configured to mark files. `content_type` is the
+/// field's `Content-Type` header, if it had one.
+pub fn classify_field(
+    cfg: &PartsConfig,
+    name: &str,
+    file_name: Option<&str>,
+    content_type: Option<&mime::Mime>,
+) -> FieldKind {
+    let marked_as_file = cfg.lists_field(cfg.file_fields.as_ref(), name);
+    let marked_as_text = cfg.lists_field(cfg.text_fields.as_ref(), name);
+
+    #[cfg(feature = "regex")]
+    let marked_as_file =
+        marked_as_file || cfg.file_field_pattern.as_ref().is_some_and(|x| x.is_match(name));
+    #[cfg(feature = "regex")]
+    let marked_as_text =
+        marked_as_text || cfg.text_field_pattern.as_ref().is_some_and(|x| x.is_match(name));
+
+    let marked_as_file = marked_as_file
+        || content_type
+            .zip(cfg.content_type_files.as_ref())
+            .is_some_and(|(content_type, x)| x.is_match(content_type));
+
+    match file_name {
+        Some(_) if !marked_as_text => FieldKind::File,
+        None if marked_as_file => FieldKind::File,
+        _ => FieldKind::Text,
+    }
+}
+
+impl std::iter::FromIterator<(String, Part)> for Parts {
+    fn from_iter<I: IntoIterator<Item = (String, Part)>>(iter: I) -> Self {
+        let mut texts = Vec::new();
+        let mut text_meta = Vec::new();
+        let mut files = Vec::new();
+        let mut file_indexes = Vec::new();
+        let mut discarded_files = Vec::new();
+        let mut failed = Vec::new();
+        for (i, (name, p)) in iter.into_iter().enumerate() {
+            match p {
+                Part::Text(s, content_type) => {
+                    texts.push((name, s));
+                    text_meta.push(TextMeta { index: i, content_type, sensitive: false });
+                }
+                Part::File(f) => {
+                    files.push((name, f));
+                    file_indexes.push(i);
+                }
+                Part::Discarded(m) => {
+                    discarded_files.push((name, m));
+                }
+                Part::Failed(e) => {
+                    failed.push((name, e));
+                }
+                Part::Skipped => {}
+            }
+        }
+        Parts {
+            texts: TextParts(texts, text_meta),
+            files: FileParts(files, file_indexes),
+            discarded_files,
+            failed,
+        }
+    }
+}
+
+#[cfg(not(feature = "v1"))]
+impl Parts {
+    /// Split back into named parts, texts first
+    pub(crate) fn into_parts(self) -> impl Iterator<Item = (String, Part)> {
+        let texts = self
+            .texts
+            .0
+            .into_iter()
+            .zip(self.texts.1)
+            .map(|((name, s), meta)| (name, Part::Text(s, meta.content_type)));
+        let files = self.files.0.into_iter().map(|(name, f)| (name, Part::File(f)));
+        let discarded =
+            self.discarded_files.into_iter().map(|(name, m)| (name, Part::Discarded(m)));
+        let failed = self.failed.into_iter().map(|(name, e)| (name, Part::Failed(e)));
+        texts.chain(files).chain(discarded).chain(failed)
+    }
+}
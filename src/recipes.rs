@@ -0,0 +1,170 @@
+//! Helpers for common handler patterns
+//!
+//! Enabled with the `recipes` feature. These work the same with every supported `actix-web`
+//! version.
+
+use super::*;
+use actix_web::error;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A file saved by `persist_all_to_dir`
+#[derive(Debug, Serialize)]
+pub struct PersistedFile {
+    pub field: String,
+    pub original_file_name: Option<String>,
+    pub path: PathBuf,
+}
+
+/// A file that could not be saved by `persist_all_to_dir`
+#[derive(Debug, Serialize)]
+pub struct FailedFile {
+    pub field: String,
+    pub original_file_name: Option<String>,
+    pub error: String,
+}
+
+/// The outcome of `persist_all_to_dir`, suitable for returning as JSON
+#[derive(Debug, Default, Serialize)]
+pub struct PersistReport {
+    pub persisted: Vec<PersistedFile>,
+    pub failed: Vec<FailedFile>,
+}
+
+/// Persist every file to an existing directory, naming them as `FileParts::persist_all_in` does
+///
+/// Unlike `persist_all_in`, this does not stop at the first failure: files that failed during
+/// parsing (e.g., too large) are reported alongside files that could not be persisted.
+pub fn persist_all_to_dir<P: AsRef<Path>>(files: FileParts, dir: P) -> PersistReport {
+    persist_all_to_dir_with(files, dir, CollisionPolicy::Overwrite)
+}
+
+/// Like `persist_all_to_dir`, but with a policy for names that are already taken (see
+/// `FileParts::persist_all_in_with`)
+pub fn persist_all_to_dir_with<P: AsRef<Path>>(
+    files: FileParts,
+    dir: P,
+    policy: CollisionPolicy,
+) -> PersistReport {
+    let mut report = PersistReport::default();
+
+    for (field, res) in files.into_inner() {
+        let file = match res {
+            Ok(file) => file,
+            Err(e) => {
+                let original_file_name = match e {
+                    Error::FileTooLarge { ref file_name, .. }
+                    | Error::DisallowedMimeType { ref file_name, .. }
+                    | Error::DisallowedExtension { ref file_name, .. } => file_name.clone(),
+                    _ => None,
+                };
+                report.failed.push(FailedFile { field, original_file_name, error: e.to_string() });
+                continue;
+            }
+        };
+
+        let original_file_name = file.original_file_name().map(String::from);
+
+        match file.persist_in_with(dir.as_ref(), policy) {
+            Ok(path) => report.persisted.push(PersistedFile { field, original_file_name, path }),
+            Err(e) => {
+                report.failed.push(FailedFile { field, original_file_name, error: e.to_string() })
+            }
+        }
+    }
+
+    report
+}
+
+/// Take the only file for the given name, or fail with 400 Bad Request if there is not
+/// exactly one
+pub fn single_file(files: &mut FileParts, key: &str) -> Result<File, actix_web::Error> {
+    let mut taken = files.take(key);
+
+    match taken.len() {
+        1 => Ok(taken.remove(0)),
+        0 => Err(error::ErrorBadRequest(format!("Missing file: {}", key))),
+        _ => Err(error::ErrorBadRequest(format!("Expected a single file: {}", key))),
+    }
+}
+
+/// Deserialize the text parts into a struct, or fail with 422 Unprocessable Entity
+pub fn texts_to_struct<T: DeserializeOwned>(texts: &TextParts) -> Result<T, actix_web::Error> {
+    Ok(texts.deserialize()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Form {
+        title: String,
+        count: u32,
+    }
+
+    #[test]
+    pub fn texts_to_struct_parses_fields() {
+        let texts =
+            TextParts::from(vec![("title".into(), "Hello".into()), ("count".into(), "3".into())]);
+
+        let form = texts_to_struct::<Form>(&texts).expect("Failed deserializing texts");
+
+        assert_eq!(form, Form { title: "Hello".into(), count: 3 });
+
+        let texts =
+            TextParts::from(vec![("title".into(), "Hello".into()), ("count".into(), "x".into())]);
+        assert!(matches!(texts.deserialize::<Form>(), Err(Error::InvalidForm { .. })));
+    }
+
+    #[test]
+    pub fn persist_all_to_dir_reports_files() {
+        let dir = tempfile::tempdir().expect("Failed creating temp dir.");
+        let mut tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+        tempfile.write_all(b"Hello").expect("Failed writing to file.");
+
+        let files = FileParts::from(vec![
+            ("upload".into(), Ok(File::new_with_file_name(tempfile, "hello.txt".into()))),
+            (
+                "other".into(),
+                Err(Error::FileTooLarge {
+                    field: "other".into(),
+                    limit: 1,
+                    received: 2,
+                    file_name: None,
+                }),
+            ),
+        ]);
+
+        let report = persist_all_to_dir(files, dir.path());
+
+        assert_eq!(report.persisted.len(), 1);
+        assert_eq!(report.persisted[0].path, dir.path().join("hello.txt"));
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].field, "other");
+    }
+
+    #[test]
+    pub fn persist_all_to_dir_with_keeps_colliding_files() {
+        let dir = tempfile::tempdir().expect("Failed creating temp dir.");
+
+        let files = (0..2)
+            .map(|_| {
+                let mut tempfile = NamedTempFile::new().expect("Failed creating temp file.");
+                tempfile.write_all(b"Hello").expect("Failed writing to file.");
+                ("upload".into(), Ok(File::new_with_file_name(tempfile, "hello.txt".into())))
+            })
+            .collect::<Vec<_>>();
+
+        let report = persist_all_to_dir_with(
+            FileParts::from(files),
+            dir.path(),
+            CollisionPolicy::AppendCounter,
+        );
+
+        assert!(report.failed.is_empty());
+        assert_eq!(report.persisted.len(), 2);
+        assert_eq!(report.persisted[0].path, dir.path().join("hello.txt"));
+        assert_ne!(report.persisted[1].path, report.persisted[0].path);
+    }
+}
@@ -0,0 +1,169 @@
+//! Verifying signed submissions
+//!
+//! Enabled with the `signature` feature. A signature is the hex-encoded HMAC-SHA256 of a
+//! canonical listing of the signed fields, with one line per field:
+//!
+//! * `t:<name>:<hex SHA-256 of the value>\n` for text fields
+//! * `f:<name>:<hex SHA-256 of the contents>\n` for file fields
+//!
+//! Lines are sorted by field name; fields sharing a name keep the order they arrived in. Files
+//! that failed while parsing (e.g., too large) are left out.
+
+use super::*;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// Where the expected signature is sent
+#[derive(Debug, Clone)]
+pub enum SignatureSource {
+    /// A text field with this name, which is itself left out of the signed fields
+    Field(String),
+    /// A request header with this name
+    Header(String),
+}
+
+/// Signature verification settings for `PartsConfig::with_signature`
+#[derive(Clone)]
+pub struct Signature {
+    key: Arc<[u8]>,
+    source: SignatureSource,
+    fields: Option<Arc<[String]>>,
+}
+
+impl std::fmt::Debug for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Signature")
+            .field("source", &self.source)
+            .field("fields", &self.fields)
+            .finish()
+    }
+}
+
+impl Signature {
+    /// Verify signatures made with this secret key, sent in `source`
+    pub fn new<K: AsRef<[u8]>>(key: K, source: SignatureSource) -> Self {
+        Signature { key: key.as_ref().into(), source, fields: None }
+    }
+
+    /// Only these fields are signed (by default, every field other than the signature field)
+    pub fn with_fields(mut self, fields: Vec<String>) -> Self {
+        self.fields = Some(fields.into());
+        self
+    }
+
+    pub(crate) fn header_name(&self) -> Option<&str> {
+        match self.source {
+            SignatureSource::Header(ref name) => Some(name),
+            SignatureSource::Field(_) => None,
+        }
+    }
+
+    fn is_signed(&self, name: &str) -> bool {
+        match (&self.fields, &self.source) {
+            (Some(fields), _) => fields.iter().any(|x| x == name),
+            (None, SignatureSource::Field(x)) => x != name,
+            (None, SignatureSource::Header(_)) => true,
+        }
+    }
+
+    /// Check the parts against the signature sent with them
+    ///
+    /// NOTE: this reads signed files from disk, so it should not be called on an async executor
+    pub(crate) fn verify(&self, parts: &Parts, header: Option<&str>) -> Result<(), Error> {
+        let expected = match self.source {
+            SignatureSource::Header(_) => header,
+            SignatureSource::Field(ref name) => {
+                parts.texts.as_hash_map().get(name.as_str()).copied()
+            }
+        };
+
+        let expected =
+            expected.and_then(|x| hex::decode(x.trim()).ok()).ok_or(Error::InvalidSignature)?;
+
+        signature_mac(parts, &self.key, |name| self.is_signed(name))?
+            .verify_slice(&expected)
+            .map_err(|_| Error::InvalidSignature)
+    }
+}
+
+/// The configured signature, with the header it may be sent in, for checking a request
+pub(crate) fn for_request(
+    cfg: &PartsConfig,
+    req: &actix_web::HttpRequest,
+) -> Option<(Signature, Option<String>)> {
+    let signature = cfg.signature.clone()?;
+    let header = signature
+        .header_name()
+        .and_then(|name| req.headers().get(name))
+        .and_then(|x| x.to_str().ok())
+        .map(String::from);
+    Some((signature, header))
+}
+
+impl Parts {
+    /// Computes the signature (see the `signature` module) of the given fields
+    ///
+    /// NOTE: this reads signed files from disk
+    pub fn signature<K: AsRef<[u8]>>(&self, key: K, fields: &[&str]) -> Result<String, Error> {
+        let mac = signature_mac(self, key.as_ref(), |name| fields.contains(&name))?;
+        Ok(hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+fn signature_mac<F: Fn(&str) -> bool>(
+    parts: &Parts,
+    key: &[u8],
+    is_signed: F,
+) -> Result<Hmac<Sha256>, Error> {
+    let mut lines = Vec::new();
+
+    for (name, val) in parts.texts.0.iter().filter(|(name, _)| is_signed(name)) {
+        lines.push((name.as_str(), 't', hex::encode(Sha256::digest(val))));
+    }
+
+    for (name, file) in parts.files.0.iter().filter(|(name, _)| is_signed(name)) {
+        if let Ok(file) = file {
+            let mut hasher = Sha256::new();
+            let mut contents = file.reader().map_err(Error::Io)?;
+            std::io::copy(&mut contents, &mut hasher).map_err(Error::Io)?;
+            lines.push((name.as_str(), 'f', hex::encode(hasher.finalize())));
+        }
+    }
+
+    lines.sort_by_key(|(name, _, _)| *name);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for (name, kind, digest) in lines {
+        mac.update(format!("{}:{}:{}\n", kind, name, digest).as_bytes());
+    }
+
+    Ok(mac)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn signature_from_field_is_verified() {
+        let mut parts = Parts {
+            texts: TextParts::from(vec![("b".into(), "2".into()), ("a".into(), "1".into())]),
+            files: FileParts::from(vec![]),
+            discarded_files: Vec::new(),
+            failed: Vec::new(),
+        };
+
+        let signature = parts.signature("secret", &["a", "b"]).expect("Failed signing parts");
+        parts.texts = TextParts::from(vec![
+            ("b".into(), "2".into()),
+            ("a".into(), "1".into()),
+            ("sig".into(), signature.into()),
+        ]);
+
+        let sig = Signature::new("secret", SignatureSource::Field("sig".into()));
+        assert!(sig.verify(&parts, None).is_ok());
+
+        let sig = Signature::new("other", SignatureSource::Field("sig".into()));
+        assert!(sig.verify(&parts, None).is_err());
+    }
+}
@@ -5,6 +5,7 @@ use futures_v01::{
     future::{self, Either},
     Future, IntoFuture, Stream,
 };
+use std::{cell::RefCell, rc::Rc};
 
 impl FromRequest for Parts {
     type Error = ActixWebError;
@@ -12,49 +13,137 @@ impl FromRequest for Parts {
     type Config = PartsConfig;
 
     fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
-        let opt_cfg = req.get_app_data::<PartsConfig>();
-
-        let rt = Multipart::from_request(req, payload)
-            .into_future()
-            .map(|mp| mp.map_err(error::ErrorInternalServerError))
-            .flatten_stream()
-            .map(move |field| handle_field(opt_cfg.clone(), field).into_stream())
-            .flatten()
-            .collect()
-            .map(|parts| {
-                let mut texts = Vec::with_capacity(parts.len());
-                let mut files = Vec::with_capacity(parts.len());
-                for (name, p) in parts.into_iter() {
-                    match p {
-                        Part::Text(s) => {
-                            texts.push((name, s));
-                        }
-                        Part::File(f) => {
-                            files.push((name, f));
-                        }
-                    }
-                }
-                Parts { texts: TextParts(texts), files: FileParts(files) }
-            });
+        let cfg = req
+            .get_app_data::<PartsConfig>()
+            .unwrap_or_else(|| web::Data::new(PartsConfig::default()));
+        let req_state = Rc::new(RefCell::new(RequestState::default()));
+
+        let content_length = req
+            .headers()
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse().ok());
+
+        let content_type = req.headers().get(http::header::CONTENT_TYPE).map(|x| x.as_bytes());
+
+        if let Err(e) = cfg.validate() {
+            return Box::new(future::err(cfg.error_response(e, req)));
+        }
+
+        if let Err(e) = check_request_content_type(content_type) {
+            return Box::new(future::err(cfg.error_response(e, req)));
+        }
+
+        if let Err(e) = cfg.check_content_length(content_length) {
+            return Box::new(future::err(cfg.error_response(e, req)));
+        }
+
+        #[cfg(feature = "signature")]
+        let signature_check = signature::for_request(&cfg, req);
+
+        let collect_cfg = cfg.clone();
+        let required_cfg = cfg.clone();
+        let error_cfg = cfg.clone();
+        let error_req = req.clone();
+
+        let rt = Multipart::from_request(req, payload).into_future().and_then(move |mp| {
+            let rt = mp
+                .map_err(|source| Error::MultipartError { field: None, source })
+                .map(move |field| handle_field(cfg.clone(), req_state.clone(), field).into_stream())
+                .flatten()
+                .fold(Vec::new(), move |mut parts, (name, part)| {
+                    push_part(&collect_cfg, &mut parts, name, part).map(|_| parts)
+                })
+                .map(|parts| parts.into_iter().collect::<Parts>())
+                .and_then(move |mut parts| {
+                    parts.texts.mark_sensitive(&required_cfg);
+                    check_required_fields(&required_cfg, &parts).map(|_| parts)
+                });
+
+            #[cfg(feature = "signature")]
+            let rt = rt.and_then(move |parts| verify_signature(signature_check, parts));
+
+            rt.map_err(move |e| error_cfg.error_response(e, &error_req))
+        });
 
         Box::new(rt)
     }
 }
 
+#[cfg(feature = "signature")]
+fn verify_signature(
+    signature_check: Option<(signature::Signature, Option<String>)>,
+    parts: Parts,
+) -> impl Future<Item = Parts, Error = Error> {
+    let (signature, header) = match signature_check {
+        Some(x) => x,
+        None => return Either::A(future::ok(parts)),
+    };
+
+    Either::B(
+        web::block(move || signature.verify(&parts, header.as_deref()).map(|_| parts)).map_err(
+            |e| match e {
+                error::BlockingError::Error(e) => e,
+                e => blocking_error(e),
+            },
+        ),
+    )
+}
+
 fn new_temp_file(
-    opt_cfg: Option<web::Data<PartsConfig>>,
-) -> impl Future<Item = NamedTempFile, Error = error::Error> {
-    web::block(move || match opt_cfg.as_ref().and_then(|x| x.temp_dir.as_ref()) {
-        Some(temp_dir) => NamedTempFile::new_in(temp_dir),
-        _ => NamedTempFile::new(),
-    })
-    .map_err(error::ErrorInternalServerError)
+    cfg: web::Data<PartsConfig>,
+    len: usize,
+) -> impl Future<Item = NamedTempFile, Error = Error> {
+    web::block(move || create_temp_file(&cfg, len)).map_err(blocking_temp_file_error)
+}
+
+fn blocking_temp_file_error(e: error::BlockingError<std::io::Error>) -> Error {
+    match e {
+        error::BlockingError::Error(e) => temp_file_error(e),
+        e => blocking_error(e),
+    }
+}
+
+/// Read the rest of a field without keeping it, still counting it toward the request limits
+fn drain_field(
+    cfg: web::Data<PartsConfig>,
+    req_state: Rc<RefCell<RequestState>>,
+    name: String,
+    field: Field,
+) -> impl Future<Item = (String, usize), Error = Error> {
+    let field_name = name.clone();
+    field
+        .map_err(move |source| Error::MultipartError { field: Some(field_name.clone()), source })
+        .fold(0, move |size, bytes| {
+            req_state.borrow_mut().add_bytes(&cfg, bytes.len()).map(|_| size + bytes.len())
+        })
+        .map(move |size| (name, size))
+}
+
+/// Read a single multipart field into a named `Part`, as the `Parts` extractor does
+#[cfg(feature = "low-level")]
+pub fn read_field(
+    cfg: web::Data<PartsConfig>,
+    req_state: Rc<RefCell<RequestState>>,
+    field: Field,
+) -> impl Future<Item = (String, Part), Error = ActixWebError> {
+    handle_field(cfg, req_state, field).map_err(ActixWebError::from)
 }
 
 fn handle_field(
-    opt_cfg: Option<web::Data<PartsConfig>>,
+    cfg: web::Data<PartsConfig>,
+    req_state: Rc<RefCell<RequestState>>,
     field: Field,
-) -> impl Future<Item = (String, Part), Error = error::Error> {
+) -> impl Future<Item = (String, Part), Error = Error> {
+    if let Err(e) = req_state.borrow_mut().add_part(&cfg) {
+        return Either::A(future::err(e));
+    }
+
+    let header_size = field.headers().iter().map(|(k, v)| k.as_str().len() + v.len() + 4).sum();
+    if let Err(e) = cfg.check_part_header_size(header_size) {
+        return Either::A(future::err(e));
+    }
+
     let mut name_opt = None;
     let mut file_name_opt = None;
 
@@ -73,71 +162,182 @@ fn handle_field(
     let name = match name_opt {
         Some(s) => s,
         None => {
-            return Either::A(future::err(error::ErrorInternalServerError(
-                "Field name is required",
-            )));
+            let e = Error::MissingFieldName { index: req_state.borrow().part_index() };
+            return Either::A(future::err(e));
         }
     };
 
+    let (name, file_name_opt) = match limit_name_lens(&cfg, name, file_name_opt) {
+        Ok(x) => x,
+        Err(e) => return Either::A(future::err(e)),
+    };
+    let name = cfg.field_name(name);
+    let cfg = cfg.for_field(&name).map(web::Data::new).unwrap_or(cfg);
+
     let mime_type = field.content_type().clone();
+    let field_headers =
+        part_headers(field.headers().iter().map(|(k, v)| (k.as_str(), v.as_bytes())));
+    let base64_encoded =
+        is_base64_encoded(field.headers().get("content-transfer-encoding").map(|x| x.as_bytes()));
+    let declared_type = match declared_content_type(
+        &cfg,
+        &name,
+        field.headers().get(http::header::CONTENT_TYPE).map(|x| x.as_bytes()),
+    ) {
+        Ok(x) => x,
+        Err(e) => {
+            let part = match cfg.fail_part(e) {
+                Ok(x) => x,
+                Err(e) => return Either::A(future::err(e)),
+            };
+            let rt = drain_field(cfg, req_state, name, field).map(move |(name, _)| (name, part));
+            return Either::B(Either::A(Either::B(rt)));
+        }
+    };
 
-    let marked_as_file = opt_cfg
-        .as_ref()
-        .iter()
-        .map(|x| x.file_fields.iter().flatten())
-        .flatten()
-        .any(|x| x == &name);
+    let field_name = name.clone();
+    let multipart_error =
+        move |source| Error::MultipartError { field: Some(field_name.clone()), source };
 
-    let marked_as_text = opt_cfg
-        .as_ref()
-        .iter()
-        .map(|x| x.text_fields.iter().flatten())
-        .flatten()
-        .any(|x| x == &name);
+    let kind = match cfg.field_action(&name, file_name_opt.as_deref(), &mime_type) {
+        FieldAction::Accept => {
+            classify_field(&cfg, &name, file_name_opt.as_deref(), declared_type.as_ref())
+        }
+        FieldAction::ForceText => FieldKind::Text,
+        FieldAction::ForceFile => FieldKind::File,
+        FieldAction::Discard => {
+            let rt =
+                drain_field(cfg, req_state, name, field).map(|(name, _)| (name, Part::Skipped));
+            return Either::B(Either::A(Either::A(Either::B(rt))));
+        }
+    };
 
-    let buffer_fut = match file_name_opt.as_ref() {
-        Some(_) if !marked_as_text => Either::A(new_temp_file(opt_cfg.clone()).map(Buffer::File)),
-        None if marked_as_file => Either::A(new_temp_file(opt_cfg.clone()).map(Buffer::File)),
-        _ => Either::B(future::ok(Buffer::Cursor(Cursor::new(Vec::new())))),
+    if let Err(e) = req_state.borrow_mut().add_field(&cfg) {
+        return Either::A(future::err(e));
+    }
+
+    if kind == FieldKind::File {
+        let admitted = admit_file(
+            &cfg,
+            &mut req_state.borrow_mut(),
+            &name,
+            &mime_type,
+            file_name_opt.as_deref(),
+        );
+
+        let drained = match admitted {
+            Ok(x) => x,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        if let Some(res) = drained {
+            let rt = drain_field(cfg, req_state, name, field).map(move |(name, size)| {
+                (name, drained_file_part(res, file_name_opt, mime_type, size))
+            });
+
+            return Either::B(Either::A(Either::A(Either::A(rt))));
+        }
+    }
+
+    let buffer_fut = match kind {
+        FieldKind::File => {
+            if cfg.in_memory_for(0) {
+                Either::B(future::ok(Buffer::Memory(Vec::new())))
+            } else {
+                Either::A(new_temp_file(cfg.clone(), 0).map(Buffer::File))
+            }
+        }
+        FieldKind::Text => Either::B(future::ok(Buffer::Cursor(Cursor::new(Vec::new())))),
     };
 
+    let (drain_cfg, drain_req_state, drain_name) = (cfg.clone(), req_state.clone(), name.clone());
+    let final_cfg = cfg.clone();
+    let field_name = name.clone();
+    let (spill_mime_type, spill_file_name) = (mime_type.clone(), file_name_opt.clone());
+    let tracker = Rc::new(RefCell::new(FileTracker::default()));
+    let final_tracker = tracker.clone();
+
     let rt =
         future::loop_fn(Either::A(buffer_fut.map(|buffer| (field, buffer, 0))), move |state| {
-            let opt_cfg = opt_cfg.clone();
+            let cfg = cfg.clone();
+            let req_state = req_state.clone();
+            let field_name = field_name.clone();
+            let (spill_mime_type, spill_file_name) =
+                (spill_mime_type.clone(), spill_file_name.clone());
+            let tracker = tracker.clone();
+            let multipart_error = multipart_error.clone();
             state.and_then(move |(stream, buffer, mut len)| {
-                let opt_cfg = opt_cfg.clone();
-                stream.into_future().map_err(|(e, _)| error::ErrorInternalServerError(e)).and_then(
+                let cfg = cfg.clone();
+                stream.into_future().map_err(move |(e, _)| multipart_error(e)).and_then(
                     move |(bytes, new_stream)| match bytes {
-                        Some(bytes) => {
-                            let opt_cfg = opt_cfg.clone();
+                        Some(mut bytes) => {
+                            let cfg = cfg.clone();
+
+                            if let Err(e) = req_state.borrow_mut().add_bytes(&cfg, bytes.len()) {
+                                return Either::B(future::ok(future::Loop::Break(future::err(e))));
+                            }
 
                             len += bytes.len();
 
                             let mut opt_cursor = None;
 
-                            let buffer_fut = if opt_cfg
-                                .as_ref()
-                                .and_then(|x| x.text_limit)
-                                .map(|x| len > x)
-                                .unwrap_or(false)
+                            let text_overflow = cfg.text_limit.map(|x| len > x).unwrap_or(false);
+
+                            if let (true, Buffer::Cursor(_), Err(e)) =
+                                (text_overflow, &buffer, cfg.check_text_overflow(&field_name))
                             {
-                                match buffer {
-                                    Buffer::Cursor(cursor) => {
+                                let rt = if cfg.collects_errors() {
+                                    future::ok(Either::A((new_stream, Either::B(Either::A(e)))))
+                                } else {
+                                    future::err(e)
+                                };
+                                return Either::B(future::ok(future::Loop::Break(rt)));
+                            }
+
+                            let buffer_fut = match buffer {
+                                Buffer::Cursor(cursor) if text_overflow => {
+                                    let admitted = admit_file(
+                                        &cfg,
+                                        &mut req_state.borrow_mut(),
+                                        &field_name,
+                                        &spill_mime_type,
+                                        spill_file_name.as_deref(),
+                                    );
+                                    match admitted {
+                                        Ok(None) => {}
+                                        Ok(Some(res)) => {
+                                            let spilled = Either::B(Either::B((res, len)));
+                                            let rt = future::ok(Either::A((new_stream, spilled)));
+                                            return Either::B(future::ok(future::Loop::Break(rt)));
+                                        }
+                                        Err(e) => {
+                                            let rt = future::err(e);
+                                            return Either::B(future::ok(future::Loop::Break(rt)));
+                                        }
+                                    }
+                                    if cfg.in_memory_for(len) {
+                                        Either::B(future::ok(Buffer::Memory(cursor.into_inner())))
+                                    } else {
                                         opt_cursor = Some(cursor);
-                                        Either::A(new_temp_file(opt_cfg.clone()).map(Buffer::File))
+                                        Either::A(new_temp_file(cfg.clone(), len).map(Buffer::File))
                                     }
-                                    x => Either::B(future::ok(x)),
                                 }
-                            } else {
-                                Either::B(future::ok(buffer))
+                                Buffer::Memory(data) if !cfg.in_memory_for(len) => {
+                                    opt_cursor = Some(Cursor::new(data));
+                                    Either::A(new_temp_file(cfg.clone(), len).map(Buffer::File))
+                                }
+                                x => Either::B(future::ok(x)),
                             };
 
                             Either::A(buffer_fut.map(move |buffer| match buffer {
                                 Buffer::Cursor(mut cursor) => {
+                                    if let Err(e) =
+                                        req_state.borrow_mut().add_text(&cfg, bytes.len())
+                                    {
+                                        return future::Loop::Break(future::err(e));
+                                    }
                                     if let Err(e) = cursor.write_all(bytes.as_ref()) {
-                                        return future::Loop::Break(future::err(
-                                            error::ErrorInternalServerError(e),
-                                        ));
+                                        return future::Loop::Break(future::err(Error::Io(e)));
                                     }
                                     future::Loop::Continue(Either::B(Either::A(future::ok((
                                         new_stream,
@@ -145,17 +345,50 @@ fn handle_field(
                                         len,
                                     )))))
                                 }
-                                Buffer::File(mut file) => {
-                                    if let Some(limit) = opt_cfg.as_ref().and_then(|x| x.file_limit)
-                                    {
-                                        if len > limit {
-                                            return future::Loop::Break(future::ok(Either::A(
-                                                FileTooLarge { limit },
-                                            )));
+                                Buffer::Memory(mut data) => {
+                                    match file_chunk_len(&cfg, bytes.len(), len) {
+                                        Ok(keep) => bytes.truncate(keep),
+                                        Err(limit) => {
+                                            return future::Loop::Break(future::ok(Either::A((
+                                                new_stream,
+                                                Either::A(FileTooLarge { limit, received: len }),
+                                            ))));
                                         }
                                     }
+                                    data.extend_from_slice(bytes.as_ref());
+                                    future::Loop::Continue(Either::B(Either::A(future::ok((
+                                        new_stream,
+                                        Buffer::Memory(data),
+                                        len,
+                                    )))))
+                                }
+                                Buffer::File(file) => {
+                                    match file_chunk_len(&cfg, bytes.len(), len) {
+                                        Ok(keep) => bytes.truncate(keep),
+                                        Err(limit) => {
+                                            return future::Loop::Break(future::ok(Either::A((
+                                                new_stream,
+                                                Either::A(FileTooLarge { limit, received: len }),
+                                            ))));
+                                        }
+                                    }
+
+                                    let cursor_len =
+                                        opt_cursor.as_ref().map_or(0, |x| x.get_ref().len());
+                                    if let Err(e) = req_state
+                                        .borrow_mut()
+                                        .add_temp_bytes(&cfg, cursor_len + bytes.len())
+                                    {
+                                        return future::Loop::Break(future::err(e));
+                                    }
+                                    if let Some(ref cursor) = opt_cursor {
+                                        tracker.borrow_mut().push(cursor.get_ref());
+                                    }
+                                    tracker.borrow_mut().push(bytes.as_ref());
 
                                     let rt = web::block(move || {
+                                        let mut file = retier_temp_file(&cfg, file, len)?;
+
                                         let cursor_bytes = opt_cursor
                                             .as_ref()
                                             .map(|x| x.get_ref().as_ref())
@@ -166,47 +399,85 @@ fn handle_field(
                                             .map(|_| Buffer::File(file))
                                     })
                                     .map(move |buffer| (new_stream, buffer, len))
-                                    .map_err(error::ErrorInternalServerError);
+                                    .map_err(blocking_temp_file_error);
                                     future::Loop::Continue(Either::B(Either::B(rt)))
                                 }
                             }))
                         }
                         None => Either::B(future::ok(future::Loop::Break(future::ok(Either::B(
-                            buffer,
+                            (buffer, len),
                         ))))),
                     },
                 )
             })
         })
         .flatten()
-        .map(move |buffer| match buffer {
-            Either::B(Buffer::Cursor(cursor)) => {
-                (name, Part::Text(Bytes::from(cursor.into_inner())))
+        .and_then(move |res| match res {
+            Either::A((_, Either::A(too_large))) if drain_cfg.rejects_oversize_files() => {
+                Either::B(future::ok(Either::A(Either::A(too_large))))
             }
-            Either::B(Buffer::File(file)) => {
-                let sanitized_file_name = match file_name_opt {
-                    Some(ref s) => sanitize_filename::sanitize(s),
-                    None => {
-                        let uuid = uuid::Uuid::new_v4().to_simple();
-                        match mime_guess::get_mime_extensions(&mime_type).and_then(|x| x.first()) {
-                            Some(ext) => format!("{}.{}", uuid, ext),
-                            None => uuid.to_string(),
+            Either::A((stream, failure)) => {
+                Either::A(drain_field(drain_cfg, drain_req_state, drain_name, stream).map(
+                    move |(_, drained)| match failure {
+                        Either::B(Either::B((res, len))) => {
+                            Either::A(Either::B(Either::B((res, len + drained))))
                         }
-                    }
-                };
-                (
-                    name,
-                    Part::File(Ok(File {
-                        inner: file,
-                        sanitized_file_name,
-                        original_file_name: file_name_opt,
-                    })),
+                        failure => Either::A(failure),
+                    },
+                ))
+            }
+            Either::B(done) => Either::B(future::ok(Either::B(done))),
+        })
+        .and_then(move |buffer| match buffer {
+            Either::B((Buffer::File(_), len)) | Either::B((Buffer::Memory(_), len))
+                if final_cfg.skips_empty_file(len, file_name_opt.as_deref()) =>
+            {
+                Ok((name, Part::Skipped))
+            }
+            Either::B((Buffer::Cursor(cursor), _)) => {
+                let part = final_cfg.text_part(
+                    &name,
+                    cursor.into_inner(),
+                    &mime_type,
+                    base64_encoded,
+                    declared_type,
+                )?;
+                Ok((name, part))
+            }
+            Either::B((Buffer::File(file), len)) => {
+                let size = written_file_len(&final_cfg, len);
+                let file = File::new_collected(
+                    file,
+                    file_name_opt,
+                    &mime_type,
+                    size,
+                    &final_tracker.borrow(),
                 )
+                .with_headers(field_headers);
+                Ok((name, Part::File(Ok(file))))
+            }
+            Either::B((Buffer::Memory(data), _)) => {
+                let file = File::new_in_memory(Bytes::from(data), file_name_opt, Some(&mime_type))
+                    .with_headers(field_headers);
+                Ok((name, Part::File(Ok(file))))
+            }
+            Either::A(Either::B(Either::A(e))) => Ok((name, Part::Failed(e))),
+            Either::A(Either::B(Either::B((res, size)))) => {
+                Ok((name, drained_file_part(res, file_name_opt, mime_type, size)))
             }
-            Either::A(FileTooLarge { limit }) => {
-                (name, Part::File(Err(Error::FileTooLarge { limit, file_name: file_name_opt })))
+            Either::A(Either::A(FileTooLarge { limit, received })) => {
+                let e = Error::FileTooLarge {
+                    field: name.clone(),
+                    limit,
+                    received,
+                    file_name: file_name_opt,
+                };
+                if final_cfg.rejects_oversize_files() {
+                    return Err(e);
+                }
+                Ok((name, Part::File(Err(e))))
             }
         });
 
-    Either::B(rt)
+    Either::B(Either::B(rt))
 }
@@ -1,8 +1,8 @@
 use super::*;
 use actix_multipart::{Field, Multipart};
-use actix_web::{dev, error, http, web, Error as ActixWebError, FromRequest, HttpRequest};
+use actix_web::{dev, error, http, rt, web, Error as ActixWebError, FromRequest, HttpRequest};
 use futures_v03::{
-    future::{Future, TryFutureExt},
+    future::{self, Future, TryFutureExt},
     stream::TryStreamExt,
 };
 
@@ -12,46 +12,208 @@ impl FromRequest for Parts {
     type Config = PartsConfig;
 
     fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
-        let opt_cfg = req.app_data::<web::Data<PartsConfig>>().cloned();
-
-        Box::pin(Multipart::from_request(req, payload).and_then(move |mp| {
-            mp.map_err(error::ErrorInternalServerError)
-                .and_then(move |field| handle_field(opt_cfg.clone(), field))
-                .try_collect::<Vec<_>>()
-                .map_ok(|parts| {
-                    let mut texts = Vec::with_capacity(parts.len());
-                    let mut files = Vec::with_capacity(parts.len());
-                    for (name, p) in parts.into_iter() {
-                        match p {
-                            Part::Text(s) => {
-                                texts.push((name, s));
-                            }
-                            Part::File(f) => {
-                                files.push((name, f));
-                            }
-                        }
-                    }
-                    Parts { texts: TextParts(texts), files: FileParts(files) }
-                })
-        }))
+        let cfg = req
+            .app_data::<web::Data<PartsConfig>>()
+            .cloned()
+            .unwrap_or_else(|| web::Data::new(PartsConfig::default()));
+
+        let content_length = req
+            .headers()
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse().ok());
+
+        let content_type = req.headers().get(http::header::CONTENT_TYPE).map(|x| x.as_bytes());
+
+        if let Err(e) = cfg.validate() {
+            return Box::pin(future::err(cfg.error_response(e, req)));
+        }
+
+        if let Err(e) = check_request_content_type(content_type) {
+            return Box::pin(future::err(cfg.error_response(e, req)));
+        }
+
+        if let Err(e) = cfg.check_content_length(content_length) {
+            return Box::pin(future::err(cfg.error_response(e, req)));
+        }
+
+        #[cfg(feature = "signature")]
+        let signature_check = signature::for_request(&cfg, req);
+
+        let (deadline_cfg, deadline_req) = (cfg.clone(), req.clone());
+        #[cfg(feature = "signature")]
+        let (signature_cfg, signature_req) = (cfg.clone(), req.clone());
+
+        let req = req.clone();
+        let fut =
+            Multipart::from_request(&req, payload).and_then(move |mp| read_parts(cfg, req, mp));
+        let fut = with_deadline(deadline_cfg, deadline_req, fut);
+
+        #[cfg(feature = "signature")]
+        let fut = fut.and_then(move |parts| {
+            verify_signature(signature_check, parts)
+                .map_err(move |e| signature_cfg.error_response(e, &signature_req))
+        });
+
+        Box::pin(fut)
+    }
+}
+
+async fn read_parts(
+    cfg: web::Data<PartsConfig>,
+    req: HttpRequest,
+    mut mp: Multipart,
+) -> Result<Parts, ActixWebError> {
+    let error_response = |e| cfg.error_response(e, &req);
+    let mut req_state = RequestState::default();
+    let mut parts = Vec::new();
+
+    while let Some(field) = next_field(&cfg, &req_state, &mut mp).await.map_err(error_response)? {
+        let (name, part) =
+            handle_field(cfg.clone(), &mut req_state, field).await.map_err(error_response)?;
+
+        if let Part::Skipped = part {
+            continue;
+        }
+
+        match cfg.field_route(&name) {
+            Some(handler) => {
+                let routed = (handler.0)(std::iter::once((name, part)).collect()).await?;
+                for (name, part) in routed.into_parts() {
+                    push_part(&cfg, &mut parts, name, part).map_err(error_response)?;
+                }
+            }
+            None => push_part(&cfg, &mut parts, name, part).map_err(error_response)?,
+        }
+    }
+
+    let mut parts = parts.into_iter().collect::<Parts>();
+    parts.texts.mark_sensitive(&cfg);
+    check_required_fields(&cfg, &parts).map_err(error_response)?;
+
+    Ok(parts)
+}
+
+async fn with_deadline<F>(
+    cfg: web::Data<PartsConfig>,
+    req: HttpRequest,
+    fut: F,
+) -> Result<Parts, ActixWebError>
+where
+    F: Future<Output = Result<Parts, ActixWebError>>,
+{
+    match cfg.deadline {
+        Some(deadline) => rt::time::timeout(deadline, fut)
+            .await
+            .map_err(|_| cfg.error_response(Error::DeadlineExceeded, &req))?,
+        None => fut.await,
+    }
+}
+
+#[cfg(feature = "signature")]
+async fn verify_signature(
+    signature_check: Option<(signature::Signature, Option<String>)>,
+    parts: Parts,
+) -> Result<Parts, Error> {
+    let (signature, header) = match signature_check {
+        Some(x) => x,
+        None => return Ok(parts),
+    };
+
+    web::block(move || signature.verify(&parts, header.as_deref()).map(|_| parts)).await.map_err(
+        |e| match e {
+            error::BlockingError::Error(e) => e,
+            e => blocking_error(e),
+        },
+    )
+}
+
+async fn new_temp_file(cfg: web::Data<PartsConfig>, len: usize) -> Result<NamedTempFile, Error> {
+    web::block(move || create_temp_file(&cfg, len)).map_err(blocking_temp_file_error).await
+}
+
+fn blocking_temp_file_error(e: error::BlockingError<std::io::Error>) -> Error {
+    match e {
+        error::BlockingError::Error(e) => temp_file_error(e),
+        e => blocking_error(e),
+    }
+}
+
+/// The next field, failing if it takes too long to arrive (see `RequestState::chunk_timeout`)
+async fn next_field(
+    cfg: &PartsConfig,
+    req_state: &RequestState,
+    mp: &mut Multipart,
+) -> Result<Option<Field>, Error> {
+    let multipart_error = |source| Error::MultipartError { field: None, source };
+
+    match req_state.chunk_timeout(cfg, None) {
+        Some((timeout, e)) => Ok(rt::time::timeout(timeout, mp.try_next())
+            .await
+            .map_err(|_| e)?
+            .map_err(multipart_error)?),
+        None => Ok(mp.try_next().await.map_err(multipart_error)?),
+    }
+}
+
+/// The next chunk of a field, after any pause for the maximum transfer rate, failing if it
+/// takes too long to arrive (see `RequestState::chunk_timeout`)
+async fn next_chunk(
+    cfg: &PartsConfig,
+    req_state: &RequestState,
+    name: &str,
+    field: &mut Field,
+) -> Result<Option<web::Bytes>, Error> {
+    if let Some(delay) = req_state.pacing_delay(cfg) {
+        rt::time::delay_for(delay).await;
+    }
+
+    let multipart_error = |source| Error::MultipartError { field: Some(name.into()), source };
+
+    match req_state.chunk_timeout(cfg, Some(name)) {
+        Some((timeout, e)) => Ok(rt::time::timeout(timeout, field.try_next())
+            .await
+            .map_err(|_| e)?
+            .map_err(multipart_error)?),
+        None => Ok(field.try_next().await.map_err(multipart_error)?),
     }
 }
 
-async fn new_temp_file(
-    opt_cfg: Option<web::Data<PartsConfig>>,
-) -> Result<NamedTempFile, error::Error> {
-    Ok(web::block(move || match opt_cfg.as_ref().and_then(|x| x.temp_dir.as_ref()) {
-        Some(temp_dir) => NamedTempFile::new_in(temp_dir),
-        _ => NamedTempFile::new(),
-    })
-    .map_err(error::ErrorInternalServerError)
-    .await?)
+/// Read the rest of a field without keeping it, still counting it toward the request limits
+async fn drain_field(
+    cfg: &PartsConfig,
+    req_state: &mut RequestState,
+    name: &str,
+    field: &mut Field,
+) -> Result<usize, Error> {
+    let mut size = 0;
+    while let Some(bytes) = next_chunk(cfg, req_state, name, field).await? {
+        req_state.add_bytes(cfg, bytes.len())?;
+        size += bytes.len();
+    }
+    Ok(size)
+}
+
+/// Read a single multipart field into a named `Part`, as the `Parts` extractor does
+#[cfg(feature = "low-level")]
+pub async fn read_field(
+    cfg: web::Data<PartsConfig>,
+    req_state: &mut RequestState,
+    field: Field,
+) -> Result<(String, Part), ActixWebError> {
+    Ok(handle_field(cfg, req_state, field).await?)
 }
 
 async fn handle_field(
-    opt_cfg: Option<web::Data<PartsConfig>>,
+    cfg: web::Data<PartsConfig>,
+    req_state: &mut RequestState,
     mut field: Field,
-) -> Result<(String, Part), error::Error> {
+) -> Result<(String, Part), Error> {
+    req_state.add_part(&cfg)?;
+
+    let header_size = field.headers().iter().map(|(k, v)| k.as_str().len() + v.len() + 4).sum();
+    cfg.check_part_header_size(header_size)?;
+
     let mut name_opt = None;
     let mut file_name_opt = None;
 
@@ -70,54 +232,120 @@ async fn handle_field(
     let name = match name_opt {
         Some(s) => s,
         None => {
-            return Err(error::ErrorInternalServerError("Field name is required"));
+            return Err(Error::MissingFieldName { index: req_state.part_index() });
         }
     };
 
+    let (name, file_name_opt) = limit_name_lens(&cfg, name, file_name_opt)?;
+    let name = cfg.field_name(name);
+    let cfg = cfg.for_field(&name).map(web::Data::new).unwrap_or(cfg);
+
     let mime_type = field.content_type().clone();
+    let field_headers =
+        part_headers(field.headers().iter().map(|(k, v)| (k.as_str(), v.as_bytes())));
+    let base64_encoded =
+        is_base64_encoded(field.headers().get("content-transfer-encoding").map(|x| x.as_bytes()));
+    let declared_type = match declared_content_type(
+        &cfg,
+        &name,
+        field.headers().get(http::header::CONTENT_TYPE).map(|x| x.as_bytes()),
+    ) {
+        Ok(x) => x,
+        Err(e) => {
+            let part = cfg.fail_part(e)?;
+            drain_field(&cfg, req_state, &name, &mut field).await?;
+            return Ok((name, part));
+        }
+    };
+
+    let kind = match cfg.field_action(&name, file_name_opt.as_deref(), &mime_type) {
+        FieldAction::Accept => {
+            classify_field(&cfg, &name, file_name_opt.as_deref(), declared_type.as_ref())
+        }
+        FieldAction::ForceText => FieldKind::Text,
+        FieldAction::ForceFile => FieldKind::File,
+        FieldAction::Discard => {
+            drain_field(&cfg, req_state, &name, &mut field).await?;
+            return Ok((name, Part::Skipped));
+        }
+    };
+
+    req_state.add_field(&cfg)?;
 
-    let marked_as_file = opt_cfg
-        .as_ref()
-        .iter()
-        .map(|x| x.file_fields.iter().flatten())
-        .flatten()
-        .any(|x| x == &name);
-
-    let marked_as_text = opt_cfg
-        .as_ref()
-        .iter()
-        .map(|x| x.text_fields.iter().flatten())
-        .flatten()
-        .any(|x| x == &name);
-
-    let mut buffer = match file_name_opt.as_ref() {
-        Some(_) if !marked_as_text => new_temp_file(opt_cfg.clone()).map_ok(Buffer::File).await?,
-        None if marked_as_file => new_temp_file(opt_cfg.clone()).map_ok(Buffer::File).await?,
-        _ => Buffer::Cursor(Cursor::new(Vec::new())),
+    if kind == FieldKind::File {
+        let admitted = admit_file(&cfg, req_state, &name, &mime_type, file_name_opt.as_deref())?;
+
+        if let Some(res) = admitted {
+            let size = drain_field(&cfg, req_state, &name, &mut field).await?;
+            return Ok((name, drained_file_part(res, file_name_opt, mime_type, size)));
+        }
+    }
+
+    let mut buffer = match kind {
+        FieldKind::File => {
+            if cfg.in_memory_for(0) {
+                Buffer::Memory(Vec::new())
+            } else {
+                new_temp_file(cfg.clone(), 0).map_ok(Buffer::File).await?
+            }
+        }
+        FieldKind::Text => Buffer::Cursor(Cursor::new(Vec::new())),
     };
 
     let mut len = 0;
+    let mut tracker = FileTracker::default();
     let mut file_too_large = None;
 
-    while let Some(bytes) = field.try_next().await? {
+    while let Some(mut bytes) = next_chunk(&cfg, req_state, &name, &mut field).await? {
+        req_state.add_bytes(&cfg, bytes.len())?;
+
         len += bytes.len();
 
         let mut opt_cursor = None;
 
-        if opt_cfg.as_ref().and_then(|x| x.text_limit).map(|x| len > x).unwrap_or(false) {
+        if cfg.text_limit.map(|x| len > x).unwrap_or(false) {
+            if let (Buffer::Cursor(_), Err(e)) = (&buffer, cfg.check_text_overflow(&name)) {
+                let part = cfg.fail_part(e)?;
+                drain_field(&cfg, req_state, &name, &mut field).await?;
+                return Ok((name, part));
+            }
+
+            if let Buffer::Cursor(_) = buffer {
+                let admitted =
+                    admit_file(&cfg, req_state, &name, &mime_type, file_name_opt.as_deref())?;
+
+                if let Some(res) = admitted {
+                    let size = len + drain_field(&cfg, req_state, &name, &mut field).await?;
+                    return Ok((name, drained_file_part(res, file_name_opt, mime_type, size)));
+                }
+            }
+
             buffer = match buffer {
+                Buffer::Cursor(cursor) if cfg.in_memory_for(len) => {
+                    Buffer::Memory(cursor.into_inner())
+                }
                 Buffer::Cursor(cursor) => {
                     opt_cursor = Some(cursor);
-                    new_temp_file(opt_cfg.clone()).map_ok(Buffer::File).await?
+                    new_temp_file(cfg.clone(), len).map_ok(Buffer::File).await?
                 }
                 x => x,
             };
         }
 
-        if let Some(limit) = opt_cfg.as_ref().and_then(|x| x.file_limit) {
-            if let Buffer::File(_) = buffer {
-                if len > limit {
-                    file_too_large = Some(FileTooLarge { limit });
+        buffer = match buffer {
+            Buffer::Memory(data) if !cfg.in_memory_for(len) => {
+                opt_cursor = Some(Cursor::new(data));
+                new_temp_file(cfg.clone(), len).map_ok(Buffer::File).await?
+            }
+            x => x,
+        };
+
+        if let Buffer::File(_) | Buffer::Memory(_) = buffer {
+            match file_chunk_len(&cfg, bytes.len(), len) {
+                Ok(0) if opt_cursor.is_none() => continue,
+                Ok(keep) => bytes.truncate(keep),
+                Err(limit) => {
+                    file_too_large = Some(FileTooLarge { limit, received: len });
                     break;
                 }
             }
@@ -125,11 +353,26 @@ async fn handle_field(
 
         buffer = match buffer {
             Buffer::Cursor(mut cursor) => {
-                cursor.write_all(bytes.as_ref()).map_err(error::ErrorInternalServerError)?;
+                req_state.add_text(&cfg, bytes.len())?;
+                cursor.write_all(bytes.as_ref()).map_err(Error::Io)?;
                 Buffer::Cursor(cursor)
             }
-            Buffer::File(mut file) => {
+            Buffer::Memory(mut data) => {
+                data.extend_from_slice(bytes.as_ref());
+                Buffer::Memory(data)
+            }
+            Buffer::File(file) => {
+                let cursor_len = opt_cursor.as_ref().map_or(0, |x| x.get_ref().len());
+                req_state.add_temp_bytes(&cfg, cursor_len + bytes.len())?;
+                if let Some(ref cursor) = opt_cursor {
+                    tracker.push(cursor.get_ref());
+                }
+                tracker.push(bytes.as_ref());
+
+                let cfg = cfg.clone();
                 web::block(move || {
+                    let mut file = retier_temp_file(&cfg, file, len)?;
+
                     let cursor_bytes =
                         opt_cursor.as_ref().map(|x| x.get_ref().as_ref()).unwrap_or_default();
 
@@ -137,19 +380,51 @@ async fn handle_field(
                         .and_then(|_| file.write_all(bytes.as_ref()))
                         .map(|_| Buffer::File(file))
                 })
-                .map_err(error::ErrorInternalServerError)
+                .map_err(blocking_temp_file_error)
                 .await?
             }
         };
     }
 
     match (file_too_large, buffer) {
-        (Some(FileTooLarge { limit }), _) => {
-            Ok((name, Part::File(Err(Error::FileTooLarge { limit, file_name: file_name_opt }))))
+        (Some(FileTooLarge { limit, received }), _) => {
+            let e = Error::FileTooLarge {
+                field: name.clone(),
+                limit,
+                received,
+                file_name: file_name_opt,
+            };
+            if cfg.rejects_oversize_files() {
+                return Err(e);
+            }
+            drain_field(&cfg, req_state, &name, &mut field).await?;
+            Ok((name, Part::File(Err(e))))
+        }
+        (None, Buffer::File(_)) | (None, Buffer::Memory(_))
+            if cfg.skips_empty_file(len, file_name_opt.as_deref()) =>
+        {
+            Ok((name, Part::Skipped))
+        }
+        (None, Buffer::Cursor(cursor)) => {
+            let part = cfg.text_part(
+                &name,
+                cursor.into_inner(),
+                &mime_type,
+                base64_encoded,
+                declared_type,
+            )?;
+            Ok((name, part))
         }
-        (None, Buffer::Cursor(cursor)) => Ok((name, Part::Text(Bytes::from(cursor.into_inner())))),
         (None, Buffer::File(file)) => {
-            Ok((name, Part::File(Ok(File::new(file, file_name_opt, Some(&mime_type))))))
+            let size = written_file_len(&cfg, len);
+            let file = File::new_collected(file, file_name_opt, &mime_type, size, &tracker)
+                .with_headers(field_headers);
+            Ok((name, Part::File(Ok(file))))
+        }
+        (None, Buffer::Memory(data)) => {
+            let file = File::new_in_memory(Bytes::from(data), file_name_opt, Some(&mime_type))
+                .with_headers(field_headers);
+            Ok((name, Part::File(Ok(file))))
         }
     }
 }